@@ -0,0 +1,315 @@
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::process::exit;
+use std::time::Duration;
+
+use qrcodegen::QrCodeEcc;
+
+use crate::{parse_size, DEFAULT_PORT};
+
+const USAGE: &str = "\
+Usage: droppa [OPTIONS]
+
+Options:
+      --port <PORT>      Port to listen on [env: DROPPA_PORT] [default: 6969]
+      --bind-ip <IP>     Address to listen on and put in the QR code [env: DROPPA_BIND_IP] [default: autodetected, listening on all of them]
+      --ipv6             Prefer an IPv6 address when autodetecting, falling back to IPv4 if there's none
+      --download-dir <DIR>
+                         Where received files are saved [env: DROPPA_DOWNLOAD_DIR] [default: ~/Downloads/droppa_files]
+      --spill-to-disk    Keep desktop uploads in temporary files under the downloads directory instead of RAM
+      --persist-desktop  Also save desktop uploads to the downloads directory, so they survive a restart
+      --max-files <N>    Keep at most N desktop uploads, turning new ones away with 507 past that [default: no limit]
+      --evict-oldest     With --max-files, drop the oldest uploads to make room instead of turning new ones away
+      --auto-extract     Unpack uploaded .zip files into a directory next to them
+      --delete-archives  Remove uploaded .zip files once --auto-extract unpacked them
+      --debug-suffix     Save mobile uploads with a `.test` suffix, for test runs
+      --password <SECRET>
+                         Require SECRET for transfers, the QR code carries it [env: DROPPA_PASSWORD]
+      --cors <ORIGINS>   Let pages from ORIGINS, comma-separated or * for any, call the API from the browser [default: same origin only]
+      --tls              Serve over HTTPS with a self-signed certificate generated at startup
+      --mdns             Advertise as `droppa._http._tcp.local` over mDNS, so you can open droppa.local
+      --qr-terminal      Print the QR code to the terminal, for when there's no browser around to open `/qr.png`
+      --qr-out <PATH>    Also save the QR code PNG to PATH
+      --idle-timeout <SECS>
+                         Shut down after SECS seconds without uploads, downloads or page loads [default: never]
+      --assets-dir <DIR>
+                         Serve the pages and scripts from DIR where it has them, re-read on every request, to theme or work on the UI without rebuilding [default: the built-in ones]
+      --max-rate <RATE>  Cap uploads and zip downloads together at RATE bytes per second, like 500K or 10M, to leave some of the link to others [default: no cap]
+      --max-rate-per-transfer
+                         Apply --max-rate to each transfer on its own instead of to all of them together
+      --progress-step <PERCENT>
+                         Report upload and zipping progress only once it moved by PERCENT, to cut down on events over slow links [default: every change, at most every 100ms]
+      --wifi-ssid <SSID> Also serve a QR code at /wifi-qr.png that joins the SSID network, so guests are on it in one scan
+      --wifi-password <PASS>
+                         The password of --wifi-ssid's WPA network, leave it out for an open one [env: DROPPA_WIFI_PASSWORD]
+      --qr-ecc <LEVEL>   QR code error correction: low, medium, quartile or high, higher scans better when printed [default: low]
+  -h, --help             Print this help and exit
+  -V, --version          Print version and exit
+";
+
+#[derive(Default)]
+pub struct Args {
+    pub port: Option::<String>,
+    pub bind_ip: Option::<String>,
+    pub ipv6: bool,
+    pub download_dir: Option::<String>,
+    pub spill_to_disk: bool,
+    pub persist_desktop: bool,
+    pub max_files: Option::<String>,
+    pub evict_oldest: bool,
+    pub auto_extract: bool,
+    pub delete_archives: bool,
+    pub debug_suffix: bool,
+    pub password: Option::<String>,
+    pub cors: Option::<String>,
+    pub tls: bool,
+    pub mdns: bool,
+    pub qr_terminal: bool,
+    pub qr_out: Option::<PathBuf>,
+    pub qr_ecc: Option::<QrCodeEcc>,
+    pub idle_timeout: Option::<String>,
+    pub assets_dir: Option::<String>,
+    pub progress_step: Option::<String>,
+    pub max_rate: Option::<String>,
+    pub max_rate_per_transfer: bool,
+    pub wifi_ssid: Option::<String>,
+    pub wifi_password: Option::<String>,
+
+    help: bool,
+    version: bool
+}
+
+fn parse_ecc(level: &str) -> Result::<QrCodeEcc, String> {
+    match level.to_ascii_lowercase().as_str() {
+        "low"      => Ok(QrCodeEcc::Low),
+        "medium"   => Ok(QrCodeEcc::Medium),
+        "quartile" => Ok(QrCodeEcc::Quartile),
+        "high"     => Ok(QrCodeEcc::High),
+        _ => Err(format!("invalid QR error correction level: `{level}`, expected low, medium, quartile or high"))
+    }
+}
+
+impl Args {
+    pub fn parse() -> Args {
+        let args = match Self::parse_from(std::env::args().skip(1)) {
+            Ok(args) => args,
+            Err(e) => {
+                eprintln!("[FATAL] {e}");
+                eprintln!("see `droppa --help` for the list of available options");
+                exit(1)
+            }
+        };
+
+        if args.help {
+            print!("{USAGE}");
+            exit(0)
+        }
+
+        if args.version {
+            println!("droppa {version}", version = env!("CARGO_PKG_VERSION"));
+            exit(0)
+        }
+
+        args
+    }
+
+    pub fn parse_from(args: impl Iterator::<Item = String>) -> Result::<Args, String> {
+        let mut args = args.peekable();
+        let mut parsed = Args::default();
+        while let Some(arg) = args.next() {
+            // Both `--flag value` and `--flag=value` are accepted
+            let (flag, value) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_owned())),
+                _ => (arg.as_str(), None)
+            };
+
+            // The next flag isn't a value, `--port --tls` is a forgotten port, not a port of `--tls`
+            let mut value = || value.clone()
+                .or_else(|| args.next_if(|next| !next.starts_with("--")))
+                .ok_or_else(|| format!("`{flag}` expects a value"));
+
+            match flag {
+                "--port"            => parsed.port = Some(value()?),
+                "--bind-ip"         => parsed.bind_ip = Some(value()?),
+                "--ipv6"            => parsed.ipv6 = true,
+                "--download-dir"    => parsed.download_dir = Some(value()?),
+                "--spill-to-disk"   => parsed.spill_to_disk = true,
+                "--persist-desktop" => parsed.persist_desktop = true,
+                "--max-files"       => parsed.max_files = Some(value()?),
+                "--evict-oldest"    => parsed.evict_oldest = true,
+                "--auto-extract"    => parsed.auto_extract = true,
+                "--delete-archives" => parsed.delete_archives = true,
+                "--debug-suffix"    => parsed.debug_suffix = true,
+                "--password"        => parsed.password = Some(value()?),
+                "--cors"            => parsed.cors = Some(value()?),
+                "--tls"             => parsed.tls = true,
+                "--mdns"            => parsed.mdns = true,
+                "--qr-terminal"     => parsed.qr_terminal = true,
+                "--qr-out"          => parsed.qr_out = Some(value()?.into()),
+                "--qr-ecc"          => parsed.qr_ecc = Some(parse_ecc(&value()?)?),
+                "--idle-timeout"    => parsed.idle_timeout = Some(value()?),
+                "--assets-dir"      => parsed.assets_dir = Some(value()?),
+                "--progress-step"   => parsed.progress_step = Some(value()?),
+                "--max-rate"        => parsed.max_rate = Some(value()?),
+                "--max-rate-per-transfer" => parsed.max_rate_per_transfer = true,
+                "--wifi-ssid"       => parsed.wifi_ssid = Some(value()?),
+                "--wifi-password"   => parsed.wifi_password = Some(value()?),
+                "-h" | "--help"     => parsed.help = true,
+                "-V" | "--version"  => parsed.version = true,
+                _ => return Err(format!("unknown argument: `{arg}`"))
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// `--password`, then `DROPPA_PASSWORD`, the latter keeps it out of `ps`. An empty one means none.
+    pub fn password(&self) -> Option::<String> {
+        self.password.clone().or_else(|| std::env::var("DROPPA_PASSWORD").ok()).filter(|password| !password.is_empty())
+    }
+
+    /// `--wifi-ssid` and `--wifi-password`, then `DROPPA_WIFI_PASSWORD`, `None` means no Wi-Fi QR code.
+    /// Exits on a password without a network to go with it, that's a forgotten `--wifi-ssid`.
+    pub fn wifi(&self) -> Option::<(String, Option::<String>)> {
+        let password = self.wifi_password.clone().or_else(|| std::env::var("DROPPA_WIFI_PASSWORD").ok()).filter(|password| !password.is_empty());
+        let Some(ssid) = self.wifi_ssid.clone().filter(|ssid| !ssid.is_empty()) else {
+            if self.wifi_password.is_some() {
+                eprintln!("[FATAL] `--wifi-password` needs `--wifi-ssid` to go with it");
+                exit(1)
+            }
+            return None
+        };
+
+        Some((ssid, password))
+    }
+
+    #[inline(always)]
+    pub fn qr_ecc(&self) -> QrCodeEcc {
+        self.qr_ecc.unwrap_or(QrCodeEcc::Low)
+    }
+
+    /// `--port`, then `DROPPA_PORT`, then the default, warning about values that aren't a valid port.
+    pub fn port(&self) -> u16 {
+        let Some(raw) = self.port.clone().or_else(|| std::env::var("DROPPA_PORT").ok()) else {
+            return DEFAULT_PORT
+        };
+
+        match raw.parse::<u16>() {
+            Ok(port) if port != 0 => port,
+            _ => {
+                log::warn!("invalid port: `{raw}`, falling back to {DEFAULT_PORT}");
+                DEFAULT_PORT
+            }
+        }
+    }
+
+    /// `--bind-ip`, then `DROPPA_BIND_IP`, `None` means autodetect. Exits if the address doesn't parse,
+    /// since silently binding somewhere else than asked would hand out an unreachable QR code.
+    pub fn bind_ip(&self) -> Option::<IpAddr> {
+        let raw = self.bind_ip.clone().or_else(|| std::env::var("DROPPA_BIND_IP").ok())?;
+        match raw.parse::<IpAddr>() {
+            Ok(ip) => Some(ip),
+            Err(e) => {
+                eprintln!("[FATAL] invalid bind IP address: `{raw}`: {e}");
+                exit(1)
+            }
+        }
+    }
+
+    /// `--cors`, `None` means same origin only. Exits on an origin that isn't `*` or `scheme://host[:port]`,
+    /// since the middleware would only fail once the server is starting.
+    pub fn cors_origins(&self) -> Option::<Vec::<String>> {
+        let raw = self.cors.as_ref()?;
+        let origins = raw.split(',').map(str::trim).filter(|origin| !origin.is_empty()).map(|origin| {
+            let valid = origin == "*" || origin.split_once("://").is_some_and(|(scheme, host)| {
+                matches!(scheme, "http" | "https") && !host.is_empty() && !host.contains('/')
+            });
+
+            if !valid {
+                eprintln!("[FATAL] invalid CORS origin: `{origin}`, expected `*` or something like `https://example.com`");
+                exit(1)
+            }
+
+            origin.trim_end_matches('/').to_owned()
+        }).collect::<Vec::<_>>();
+
+        (!origins.is_empty()).then_some(origins)
+    }
+
+    /// `--max-files`, `None` means no limit. Exits if it's not a positive number.
+    pub fn max_files(&self) -> Option::<usize> {
+        let raw = self.max_files.as_ref()?;
+        match raw.parse::<usize>() {
+            Ok(max) if max > 0 => Some(max),
+            _ => {
+                eprintln!("[FATAL] invalid maximum number of files: `{raw}`, expected a positive number");
+                exit(1)
+            }
+        }
+    }
+
+    /// `--idle-timeout`, `None` means never. Exits if it's not a number of seconds, 0 turns it off.
+    pub fn idle_timeout(&self) -> Option::<Duration> {
+        let raw = self.idle_timeout.as_ref()?;
+        match raw.parse::<u64>() {
+            Ok(0) => None,
+            Ok(secs) => Some(Duration::from_secs(secs)),
+            Err(e) => {
+                eprintln!("[FATAL] invalid idle timeout: `{raw}`: {e}");
+                exit(1)
+            }
+        }
+    }
+
+    /// `--progress-step`, `None` means every change. Exits if it's not a percentage from 1 to 100.
+    pub fn progress_step(&self) -> Option::<u8> {
+        let raw = self.progress_step.as_ref()?;
+        match raw.trim_end_matches('%').parse::<u8>() {
+            Ok(step @ 1..=100) => Some(step),
+            _ => {
+                eprintln!("[FATAL] invalid progress step: `{raw}`, expected a percentage from 1 to 100");
+                exit(1)
+            }
+        }
+    }
+
+    /// `--max-rate`, `None` means no cap. Exits if it's not a size, per second, or if it's 0.
+    pub fn max_rate(&self) -> Option::<usize> {
+        let raw = self.max_rate.as_ref()?;
+        match parse_size(raw.trim_end_matches("/s")) {
+            Some(rate) if rate > 0 => Some(rate),
+            _ => {
+                eprintln!("[FATAL] invalid maximum rate: `{raw}`, expected bytes per second, like 500K or 10M");
+                exit(1)
+            }
+        }
+    }
+
+    /// `--download-dir`, then `DROPPA_DOWNLOAD_DIR`, `None` means the default one. Relative paths are taken
+    /// from the current directory, so the one printed at startup is the one files actually end up in.
+    pub fn download_dir(&self) -> Option::<PathBuf> {
+        let raw = self.download_dir.clone().or_else(|| std::env::var("DROPPA_DOWNLOAD_DIR").ok())?;
+        match std::path::absolute(&raw) {
+            Ok(dir) => Some(dir),
+            Err(e) => {
+                eprintln!("[FATAL] invalid download directory: `{raw}`: {e}");
+                exit(1)
+            }
+        }
+    }
+    /// `--assets-dir`, `None` means the built-in pages. Exits if it's not a directory, a typo would otherwise
+    /// quietly serve the built-in ones.
+    pub fn assets_dir(&self) -> Option::<PathBuf> {
+        let raw = self.assets_dir.as_ref()?;
+        match std::path::absolute(raw) {
+            Ok(dir) if dir.is_dir() => Some(dir),
+            Ok(..) => {
+                eprintln!("[FATAL] invalid assets directory: `{raw}`: not a directory");
+                exit(1)
+            }
+            Err(e) => {
+                eprintln!("[FATAL] invalid assets directory: `{raw}`: {e}");
+                exit(1)
+            }
+        }
+    }
+}
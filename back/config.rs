@@ -0,0 +1,189 @@
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::cli::Args;
+use crate::{parse_size, format_size, DEFAULT_PORT, DEFAULT_MEM_BUDGET, DEFAULT_SIZE_LIMIT, DEFAULT_BASE64_LIMIT, DEFAULT_MAX_UPLOADS, DEFAULT_UPLOAD_RATE, DROPPA_DOWNLOADS_DIR, DEFAULT_DRAIN_TIMEOUT_SECS};
+
+// The knobs that `--flags` and `DROPPA_*` variables turn, resolved once at startup. Flags win over variables,
+// invalid values are warned about and replaced with the defaults, which are what droppa does without any of them.
+pub struct Config {
+    pub port: u16,
+    // `None` means autodetect, and listen on all addresses
+    pub bind_ip: Option::<IpAddr>,
+    pub prefer_ipv6: bool,
+    pub downloads_dir: PathBuf,
+    pub size_limit: usize,
+    pub memory_budget: usize,
+    // The largest file `/file/{name}/base64` serves
+    pub base64_limit: usize,
+    pub max_uploads: usize,
+    pub tls: bool,
+    // Never empty, an empty one means none
+    pub password: Option::<String>,
+    // Overrides the built-in pages and scripts, file by file
+    pub assets_dir: Option::<PathBuf>,
+    // How many percent progress has to move by to be reported, `None` means any change, see `progress_due`
+    pub progress_step: Option::<u8>,
+    // Bytes per second, shared by all the transfers unless `max_rate_per_transfer`
+    pub max_rate: Option::<usize>,
+    pub max_rate_per_transfer: bool,
+    // Desktop uploads go to temporary files under `downloads_dir` instead of RAM
+    pub spill_to_disk: bool,
+    // Desktop uploads are also written to `downloads_dir`, like the mobile ones
+    pub persist_desktop: bool,
+    // `--max-files`, how many desktop uploads are kept around, past that new ones are turned away,
+    // or with `--evict-oldest` the oldest ones make room for them
+    pub max_files: Option::<usize>,
+    pub evict_oldest: bool,
+    // `--auto-extract`, uploaded `.zip`s are unpacked next to them, and removed afterwards with `--delete-archives`
+    pub auto_extract: bool,
+    pub delete_archives: bool,
+    // `--debug-suffix`, mobile uploads are saved as `name.test`, so test runs are easy to tell apart and clean up
+    pub debug_suffix: bool,
+    // How long SIGINT/SIGTERM and `/admin/drain` wait for the running transfers
+    pub drain_timeout: Duration,
+    // Uploads per second per client IP, 0 means no limit
+    pub upload_rate: f64,
+    // `/admin/drain` is off without it, never empty
+    pub admin_pin: Option::<String>,
+    // Concurrent zips take turns instead of racing for the CPU
+    pub fair_downloads: bool,
+    // HEIC uploads are converted to JPEG, with `keep_heic` the original is kept next to it
+    pub convert_heic: bool,
+    #[cfg(feature = "heic")]
+    pub keep_heic: bool
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            port: DEFAULT_PORT,
+            bind_ip: None,
+            prefer_ipv6: false,
+            downloads_dir: default_downloads_dir(),
+            size_limit: DEFAULT_SIZE_LIMIT,
+            memory_budget: DEFAULT_MEM_BUDGET,
+            base64_limit: DEFAULT_BASE64_LIMIT,
+            max_uploads: DEFAULT_MAX_UPLOADS,
+            tls: false,
+            password: None,
+            assets_dir: None,
+            progress_step: None,
+            max_rate: None,
+            max_rate_per_transfer: false,
+            spill_to_disk: false,
+            persist_desktop: false,
+            max_files: None,
+            evict_oldest: false,
+            auto_extract: false,
+            delete_archives: false,
+            debug_suffix: false,
+            drain_timeout: Duration::from_secs(DEFAULT_DRAIN_TIMEOUT_SECS),
+            upload_rate: DEFAULT_UPLOAD_RATE,
+            admin_pin: None,
+            fair_downloads: false,
+            convert_heic: false,
+            #[cfg(feature = "heic")]
+            keep_heic: false
+        }
+    }
+}
+
+impl Config {
+    pub fn load(args: &Args) -> Self {
+        let size_limit = match std::env::var("DROPPA_MAX_SIZE") {
+            Ok(raw) => parse_size(&raw).unwrap_or_else(|| {
+                log::warn!("invalid DROPPA_MAX_SIZE: `{raw}`, falling back to {limit}", limit = format_size(DEFAULT_SIZE_LIMIT));
+                DEFAULT_SIZE_LIMIT
+            }),
+            Err(_) => DEFAULT_SIZE_LIMIT
+        };
+
+        let max_uploads = match std::env::var("DROPPA_MAX_UPLOADS") {
+            Ok(raw) => raw.parse::<usize>().ok().filter(|max| *max > 0).unwrap_or_else(|| {
+                log::warn!("invalid DROPPA_MAX_UPLOADS: `{raw}`, falling back to {DEFAULT_MAX_UPLOADS}");
+                DEFAULT_MAX_UPLOADS
+            }),
+            Err(_) => DEFAULT_MAX_UPLOADS
+        };
+
+        let memory_budget = match std::env::var("DROPPA_MEM_BUDGET") {
+            Ok(raw) => parse_size(&raw).unwrap_or_else(|| {
+                log::warn!("invalid DROPPA_MEM_BUDGET: `{raw}`, falling back to {budget}", budget = format_size(DEFAULT_MEM_BUDGET));
+                DEFAULT_MEM_BUDGET
+            }),
+            Err(_) => DEFAULT_MEM_BUDGET
+        };
+
+        let base64_limit = match std::env::var("DROPPA_BASE64_LIMIT") {
+            Ok(raw) => parse_size(&raw).unwrap_or_else(|| {
+                log::warn!("invalid DROPPA_BASE64_LIMIT: `{raw}`, falling back to {limit}", limit = format_size(DEFAULT_BASE64_LIMIT));
+                DEFAULT_BASE64_LIMIT
+            }),
+            Err(_) => DEFAULT_BASE64_LIMIT
+        };
+
+        let drain_timeout = match std::env::var("DROPPA_DRAIN_TIMEOUT") {
+            Ok(raw) => raw.parse::<u64>().unwrap_or_else(|_| {
+                log::warn!("invalid DROPPA_DRAIN_TIMEOUT: `{raw}`, falling back to {DEFAULT_DRAIN_TIMEOUT_SECS}s");
+                DEFAULT_DRAIN_TIMEOUT_SECS
+            }),
+            Err(_) => DEFAULT_DRAIN_TIMEOUT_SECS
+        };
+
+        let upload_rate = match std::env::var("DROPPA_UPLOAD_RATE") {
+            Ok(raw) => raw.parse::<f64>().ok().filter(|rate| rate.is_finite() && *rate >= 0.0).unwrap_or_else(|| {
+                log::warn!("invalid DROPPA_UPLOAD_RATE: `{raw}`, falling back to {DEFAULT_UPLOAD_RATE}");
+                DEFAULT_UPLOAD_RATE
+            }),
+            Err(_) => DEFAULT_UPLOAD_RATE
+        };
+
+        Self {
+            port: args.port(),
+            bind_ip: args.bind_ip(),
+            prefer_ipv6: args.ipv6,
+            downloads_dir: args.download_dir().unwrap_or_else(default_downloads_dir),
+            size_limit,
+            memory_budget,
+            base64_limit,
+            max_uploads,
+            tls: args.tls,
+            password: args.password(),
+            assets_dir: args.assets_dir(),
+            progress_step: args.progress_step(),
+            max_rate: args.max_rate(),
+            max_rate_per_transfer: args.max_rate_per_transfer,
+            spill_to_disk: args.spill_to_disk,
+            persist_desktop: args.persist_desktop,
+            max_files: args.max_files(),
+            evict_oldest: args.evict_oldest,
+            auto_extract: args.auto_extract,
+            delete_archives: args.delete_archives,
+            debug_suffix: args.debug_suffix,
+            drain_timeout: Duration::from_secs(drain_timeout),
+            upload_rate,
+            admin_pin: std::env::var("DROPPA_ADMIN_PIN").ok().filter(|pin| !pin.is_empty()),
+            fair_downloads: env_flag("DROPPA_FAIR_DOWNLOADS"),
+            convert_heic: env_flag("DROPPA_CONVERT_HEIC"),
+            #[cfg(feature = "heic")]
+            keep_heic: env_flag("DROPPA_KEEP_HEIC")
+        }
+    }
+}
+
+// On when set to anything but `0`
+#[inline]
+fn env_flag(name: &str) -> bool {
+    std::env::var(name).is_ok_and(|value| value != "0")
+}
+
+fn default_downloads_dir() -> PathBuf {
+    // Headless boxes often don't have one
+    let parent = dirs::download_dir().unwrap_or_else(|| {
+        log::warn!("could not get user's `Downloads` directory, using the current one");
+        std::env::current_dir().expect("could not get current directory")
+    });
+    parent.join(DROPPA_DOWNLOADS_DIR)
+}
@@ -0,0 +1,159 @@
+use std::path::Path;
+
+use actix_web::web;
+use sha2::{Sha256, Digest};
+use libheif_rs::{LibHeif, HeifContext, ColorSpace, RgbChroma};
+
+use crate::{hex, File, FileData};
+use crate::stb_image_write::stbi_write_jpg_to_func;
+
+const JPEG_QUALITY: i32 = 90;
+
+// Every HEIF flavour iPhones and friends produce starts with an ISO-BMFF `ftyp` box with one of these brands
+const HEIF_BRANDS: &[&[u8; 4]] = &[b"heic", b"heix", b"heim", b"heis", b"hevc", b"hevx", b"mif1", b"msf1"];
+
+pub struct Conversion {
+    pub keep_original: bool
+}
+
+pub fn is_heic(name: &str, bytes: &[u8]) -> bool {
+    let ext = Path::new(name).extension().and_then(|ext| ext.to_str()).is_some_and(|ext| {
+        ext.eq_ignore_ascii_case("heic") || ext.eq_ignore_ascii_case("heif")
+    });
+
+    let magic = bytes.get(4..12).is_some_and(|b| {
+        &b[..4] == b"ftyp" && HEIF_BRANDS.iter().any(|brand| &b[4..] == *brand)
+    });
+
+    ext || magic
+}
+
+fn append_to_vec(ctx: *mut u8, data: *mut u8, len: i32) {
+    let out = unsafe { &mut *(ctx as *mut Vec::<u8>) };
+    out.extend_from_slice(unsafe { std::slice::from_raw_parts(data, len as usize) })
+}
+
+fn heic_to_jpeg(bytes: &[u8]) -> Result::<Vec::<u8>, String> {
+    let ctx = HeifContext::read_from_bytes(bytes).map_err(|e| e.to_string())?;
+    let handle = ctx.primary_image_handle().map_err(|e| e.to_string())?;
+    let image = LibHeif::new().decode(&handle, ColorSpace::Rgb(RgbChroma::Rgb), None).map_err(|e| e.to_string())?;
+
+    let Some(plane) = image.planes().interleaved else {
+        return Err("decoded image has no interleaved RGB plane".to_owned())
+    };
+
+    // libheif may pad rows, stb wants them tightly packed
+    let row = plane.width as usize * 3;
+    let pixels = plane.data.chunks(plane.stride).take(plane.height as usize).flat_map(|r| &r[..row]).copied().collect::<Vec::<_>>();
+
+    let mut out = Vec::new();
+    let ok = unsafe {
+        stbi_write_jpg_to_func(
+            append_to_vec,
+            &mut out as *mut Vec::<u8> as *mut u8,
+            plane.width as _,
+            plane.height as _,
+            3,
+            pixels.as_ptr(),
+            JPEG_QUALITY
+        )
+    };
+
+    if ok == 0 {
+        return Err("could not encode JPEG".to_owned())
+    }
+
+    Ok(out)
+}
+
+/// Transcodes `file` to JPEG if it's a HEIC image, otherwise hands it back as is.
+/// If the conversion fails the original is kept, since a HEIC is still better than nothing.
+pub fn convert(file: File, conversion: &Conversion) -> Vec::<File> {
+    let bytes = match file.data.read() {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            log::error!("could not read: {name}: {e}, keeping it as is", name = file.name);
+            return vec![file]
+        }
+    };
+
+    if !is_heic(&file.name, &bytes) {
+        return vec![file]
+    }
+
+    log::info!("converting HEIC: {name} to JPEG..", name = file.name);
+
+    let jpeg = match heic_to_jpeg(&bytes) {
+        Ok(jpeg) => jpeg,
+        Err(e) => {
+            log::error!("could not convert: {name}: {e}, keeping the original", name = file.name);
+            return vec![file]
+        }
+    };
+
+    let name = Path::new(&file.name).with_extension("jpg").to_string_lossy().into_owned();
+    log::info!("converted: {original} to: {name}", original = file.name);
+
+    let converted = File {
+        size: jpeg.len(),
+        sha256: hex(&Sha256::digest(&jpeg)),
+        name,
+        data: FileData::Memory(web::Bytes::from(jpeg)),
+        label: file.label.clone(),
+        // Close enough, and it stays claimed if the original is dropped
+        claim: file.claim.clone(),
+        modified: file.modified,
+        dir: file.dir.clone(),
+        mime: Some("image/jpeg")
+    };
+
+    if conversion.keep_original {
+        vec![converted, file]
+    } else {
+        vec![converted]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::SystemTime;
+
+    fn file(name: &str, bytes: &'static [u8]) -> File {
+        File {
+            size: bytes.len(),
+            name: name.to_owned(),
+            data: FileData::Memory(web::Bytes::from_static(bytes)),
+            label: None,
+            sha256: hex(&Sha256::digest(bytes)),
+            claim: None,
+            modified: SystemTime::now(),
+            dir: None,
+            mime: None
+        }
+    }
+
+    #[test]
+    fn heic_is_told_by_extension_or_brand() {
+        assert!(is_heic("IMG_0001.HEIC", b""));
+        assert!(is_heic("photo.heif", b""));
+        assert!(is_heic("photo", b"\0\0\0\x18ftypheic\0\0\0\0"));
+        assert!(is_heic("photo.bin", b"\0\0\0\x18ftypmif1\0\0\0\0"));
+        assert!(!is_heic("photo.jpg", b"\xFF\xD8\xFF\xE0"));
+        assert!(!is_heic("clip.mp4", b"\0\0\0\x18ftypisom\0\0\0\0"));
+    }
+
+    #[test]
+    fn other_files_are_handed_back_as_is() {
+        let converted = convert(file("a.txt", b"hi\n"), &Conversion { keep_original: false });
+        assert_eq!(converted.len(), 1);
+        assert_eq!(converted[0].name, "a.txt");
+    }
+
+    #[test]
+    fn broken_heic_is_kept() {
+        let converted = convert(file("IMG_0001.heic", b"\0\0\0\x18ftypheic\0\0\0\0garbage"), &Conversion { keep_original: false });
+        assert_eq!(converted.len(), 1);
+        assert_eq!(converted[0].name, "IMG_0001.heic");
+    }
+}
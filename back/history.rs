@@ -0,0 +1,101 @@
+use std::fs;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::SystemTime;
+use std::path::{Path, PathBuf};
+
+use serde::{Serialize, Deserialize};
+
+const FILE_NAME: &str = "history.json";
+
+// Past this many entries, `history.json` becomes `history.old.json` and starts over
+const MAX_ENTRIES: usize = 1000;
+
+#[derive(Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Upload,
+    Download
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Entry {
+    // Seconds since the Unix epoch
+    pub timestamp: u64,
+    pub name: String,
+    pub size: usize,
+    pub direction: Direction,
+    pub peer: Option::<IpAddr>
+}
+
+impl Entry {
+    #[inline]
+    pub fn new(direction: Direction, name: String, size: usize, peer: Option::<IpAddr>) -> Self {
+        let timestamp = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map_or(0, |since| since.as_secs());
+        Self { timestamp, name, size, direction, peer }
+    }
+}
+
+/// What was transferred, kept in `history.json` in the downloads directory so that it outlives the process.
+pub struct History {
+    // `None` when there's a `history.json` that isn't ours, then it's left alone and nothing is kept
+    path: Option::<PathBuf>,
+    entries: Mutex::<Vec::<Entry>>
+}
+
+impl History {
+    /// Picks up what previous runs left in `dir`.
+    pub fn load(dir: &Path) -> Self {
+        let path = dir.join(FILE_NAME);
+        let entries = match fs::read(&path) {
+            Ok(bytes) => match serde_json::from_slice::<Vec::<Entry>>(&bytes) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    log::warn!("could not parse: {path}: {e}, leaving it alone, transfers won't be recorded", path = path.display());
+                    return Self { path: None, entries: Mutex::new(Vec::new()) }
+                }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                log::warn!("could not read: {path}: {e}, starting a new one", path = path.display());
+                Vec::new()
+            }
+        };
+
+        Self { path: Some(path), entries: Mutex::new(entries) }
+    }
+
+    #[inline]
+    pub fn entries(&self) -> Vec::<Entry> {
+        self.entries.lock().unwrap().clone()
+    }
+
+    /// Blocking, the whole file is rewritten every time, `MAX_ENTRIES` keeps that cheap.
+    pub fn record(&self, new: Vec::<Entry>) {
+        let Some(path) = self.path.as_ref() else {
+            return
+        };
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() + new.len() > MAX_ENTRIES {
+            let old = path.with_extension("old.json");
+            match fs::rename(path, &old) {
+                Ok(()) => log::info!("moved transfer history to: {old}, starting a new one", old = old.display()),
+                Err(e) => log::warn!("could not move transfer history to: {old}: {e}, starting a new one", old = old.display())
+            }
+            entries.clear()
+        }
+
+        entries.extend(new);
+
+        // Written next to it and renamed over it, so that a crash halfway through doesn't lose what was there
+        let tmp = path.with_extension("json.tmp");
+        let written = serde_json::to_vec(&*entries).map_err(std::io::Error::other)
+            .and_then(|json| fs::write(&tmp, json))
+            .and_then(|()| fs::rename(&tmp, path));
+
+        if let Err(e) = written {
+            log::error!("could not save transfer history to: {path}: {e}", path = path.display())
+        }
+    }
+}
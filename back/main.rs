@@ -1,23 +1,31 @@
 use std::fs;
+use std::borrow::Cow;
 use std::path::PathBuf;
 use std::future::Future;
-use std::net::{IpAddr, UdpSocket};
-use std::io::{Cursor, Write, BufWriter};
-use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Instant, SystemTime};
+use std::hash::{Hash, Hasher, DefaultHasher};
+use std::io::{Read, Seek, Write, SeekFrom, BufWriter};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex, MutexGuard, atomic::{AtomicBool, AtomicUsize, Ordering}};
 
-use serde::Serialize;
-use dashmap::DashMap;
+use actix_rt::signal;
+use actix_cors::Cors;
+use sha2::{Sha256, Digest};
 use actix_web::rt as actix_rt;
+use actix_multipart::Multipart;
+use dashmap::{DashMap, DashSet};
 use qrcodegen::{QrCode, QrCodeEcc};
-use actix_files::Files as ActixFiles;
-use actix_web::{get, post, HttpRequest};
-use tokio_stream::wrappers::WatchStream;
-use futures_util::{StreamExt, TryStreamExt};
-use actix_multipart::{Multipart, MultipartError};
-use zip::{ZipWriter, CompressionMethod, write::SimpleFileOptions};
+use serde::{Serialize, Deserialize};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use tokio_stream::wrappers::{WatchStream, ReceiverStream};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use actix_files::{HttpRange, NamedFile, Files as ActixFiles};
 use tokio::time::{sleep as tokio_sleep, Duration as TokioDuration};
-use tokio::sync::{mpsc, watch, Mutex as TokioMutex, MutexGuard as TokioMutexGuard};
-use actix_web::{App, HttpServer, HttpResponse, Responder, middleware::Logger, web::{self, Path, Data}};
+use futures_util::{StreamExt, TryStreamExt, future::{self, Either}};
+use zip::{AesMode, ZipWriter, CompressionMethod, write::SimpleFileOptions};
+use tokio::sync::{mpsc, watch, Notify, Semaphore, OwnedSemaphorePermit, Mutex as TokioMutex, MutexGuard as TokioMutexGuard};
+use actix_web::{App, HttpServer, HttpResponse, Responder, middleware::{Logger, Compress, Condition}, web::{self, Path, Data, Query}};
+use actix_web::{get, put, post, routes, delete, HttpRequest, http::{StatusCode, header::{Header, EntityTag, IfNoneMatch, ContentDisposition, ContentEncoding}}};
 
 #[allow(unused_imports, unused_parens, non_camel_case_types, unused_mut, dead_code, unused_assignments, unused_variables, static_mut_refs, non_snake_case, non_upper_case_globals)]
 mod stb_image_write;
@@ -25,6 +33,27 @@ mod stb_image_write;
 mod qr;
 use qr::*;
 
+mod cli;
+use cli::Args;
+
+mod config;
+use config::Config;
+
+mod tls;
+mod mdns;
+
+mod rate_limit;
+use rate_limit::RateLimiter;
+
+mod history;
+use history::{History, Direction};
+
+mod throttle;
+use throttle::Throttle;
+
+#[cfg(feature = "heic")]
+mod heic;
+
 macro_rules! atomic_type {
     ($(type $name: ident = $ty: ty;)*) => {$(paste::paste! {
         #[allow(unused)] type $name = $ty;
@@ -49,17 +78,72 @@ macro_rules! lock_fn {
     })*};
 }
 
-const PORT: u16 = 6969;
+const DEFAULT_PORT: u16 = 6969;
+
+// `--idle-timeout` starts warning this many seconds before it shuts down
+const IDLE_WARNING_SECS: u64 = 30;
 
 const GIG: usize = 1024 * 1024 * 1024;
-const SIZE_LIMIT: usize = GIG * 3;
+const DEFAULT_SIZE_LIMIT: usize = GIG * 3;
+const DEFAULT_MEM_BUDGET: usize = 512 * 1024 * 1024;
+
+// Past this, `/file/{name}/base64` turns files away, a third bigger once encoded and all of it in one response
+const DEFAULT_BASE64_LIMIT: usize = 1024 * 1024;
+
+const DEFAULT_COMPRESSION_LEVEL: i64 = 8;
+
+// How much a zip task may compress before handing the turn over when fair downloads are on.
+const FAIR_ZIP_SLICE: usize = 64 * 1024;
+
+const WS_PROGRESS_VERSION: u8 = 1;
+
+const MAX_LABEL_LEN: usize = 32;
+
+// Most filesystems take 255 bytes, what's left is room for ` (n)`, `.part` and `.test`
+const MAX_FILE_NAME_LEN: usize = 240;
+
+const DEFAULT_DRAIN_TIMEOUT_SECS: u64 = 60;
+
+const DEFAULT_UPLOAD_RATE: f64 = 10.0;
 
-const DELIM: &str = if cfg!(windows) { "\\" } else { "/" };
+const DEFAULT_MAX_UPLOADS: usize = 4;
+
+const SSE_KEEPALIVE_SECS: u64 = 15;
+
+const PASSWORD_HEADER: &str = "X-Droppa-Token";
+
+const ZIP_PASSWORD_HEADER: &str = "X-Zip-Password";
+
+// Snippets are for links and paragraphs, anything bigger can go as a file
+const MAX_CLIPBOARD_LEN: usize = 8 * 1024;
+
+// An append is a few lines of a log, anything bigger goes as an upload. Held in RAM until it's written,
+// so that appends to the same file don't interleave, counted against `DROPPA_MEM_BUDGET` meanwhile.
+const MAX_APPEND_LEN: usize = 1024 * 1024;
+
+const DEFAULT_TAIL_LINES: usize = 10;
+const MAX_TAIL_LINES: usize = 10_000;
+
+// How often progress is reported at most
+const PROGRESS_INTERVAL_MS: u64 = 100;
+
+// How often progress clients nobody watches anymore are swept
+const CLIENT_SWEEP_SECS: u64 = 60;
+
+// Enough leading bytes for every signature `sniff` knows
+const SNIFF_LEN: usize = 12;
+
+const SPILL_DIR: &str = ".droppa-tmp";
 
 const DROPPA_DOWNLOADS_DIR: &str = "droppa_files";
 
+// What the archive is called on the desktop's progress stream, the phone names it on its own
+const ZIP_FILE_NAME: &str = "droppa_files.zip";
+
 const HOME_MOBILE_HTML:  &[u8] = include_bytes!("../front/index-mobile.html");
 const HOME_DESKTOP_HTML: &[u8] = include_bytes!("../front/index-desktop.html");
+const NOT_FOUND_HTML:    &[u8] = include_bytes!("../front/404.html");
+const FAVICON:           &[u8] = include_bytes!("../front/droppa.ico");
 
 #[derive(Debug, Serialize)]
 pub struct TrackFile {
@@ -68,367 +152,3213 @@ pub struct TrackFile {
     pub progress: u8
 }
 
+#[derive(Copy, Clone, Default, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UploadState {
+    #[default]
+    Uploading,
+    // Held by `/pause/{file_name}` until `/resume/{file_name}`
+    Paused,
+    Cancelled,
+    // Received in full
+    Done,
+    Failed
+}
+
+// What `/progress/{file_name}` and `/zipping-progress` stream.
+#[derive(Copy, Clone, Default, Serialize)]
+pub struct ProgressEvent {
+    progress: u8,
+    // Bytes per second
+    bps: u64,
+    // `null` until there's at least a second worth of data to estimate from
+    eta_secs: Option::<u64>,
+    // How uploads are going, and how zips ended
+    #[serde(skip_serializing_if = "Option::is_none")]
+    state: Option::<UploadState>
+}
+
+// Transfer rate of something that's `total` bytes big, sampled every time progress is reported.
+#[derive(Copy, Clone)]
+pub struct Rate {
+    started: Instant,
+    last: (Instant, usize),
+    bps: f64
+}
+
+impl Rate {
+    // `done` is what's already there, e.g. the part of a resumed upload that was received before
+    #[inline(always)]
+    pub fn new(done: usize) -> Self {
+        let now = Instant::now();
+        Self { started: now, last: (now, done), bps: 0.0 }
+    }
+
+    pub fn sample(&mut self, done: usize, total: usize) -> (u64, Option::<u64>) {
+        let now = Instant::now();
+        let (last, last_done) = self.last;
+        let dt = now.duration_since(last).as_secs_f64();
+        if dt > 0.0 {
+            let bps = done.saturating_sub(last_done) as f64 / dt;
+            // Smoothed, so that a single slow or fast write doesn't make the numbers jump around
+            self.bps = if self.bps == 0.0 { bps } else { self.bps * 0.7 + bps * 0.3 };
+            self.last = (now, done)
+        }
+
+        let eta = (self.started.elapsed().as_secs() >= 1 && self.bps > 0.0).then(|| {
+            (total.saturating_sub(done) as f64 / self.bps).ceil() as u64
+        });
+
+        (self.bps as u64, eta)
+    }
+}
+
 pub struct Client {
-    sender: watch::Sender::<u8>,
+    sender: watch::Sender::<ProgressEvent>,
     progress: u8,
     mobile: bool,
     size: usize,
+    received: usize,
+    // Set while an upload of this file is running, `/cancel/{file_name}` flips it
+    cancel: Option::<Arc::<AtomicBool>>,
+    // Set along with `cancel`, `/pause/{file_name}` and `/resume/{file_name}` flip it
+    pause: Option::<watch::Sender::<bool>>,
+    // When `progress` was last sent
+    last_update: Option::<Instant>,
+    rate: Rate
 }
 
 atomic_type! {
     type Files = Vec::<File>;
-    type SyncProgressSender = Option::<mpsc::Sender::<u8>>;
+    type QrBytes = web::Bytes;
+    type LastActivity = Instant;
+    type QrTarget = ServerInfo;
+    type SyncProgressSender = Option::<mpsc::Sender::<ProgressEvent>>;
 }
 
 atomic_type! {
     tokio.type ProgressPinger = Option::<mpsc::Sender::<()>>;
     tokio.type ProgressStreamer = Option::<watch::Sender::<String>>;
+    tokio.type ZipTurn = ();
 }
 
 atomic_type! {
     arc.type Clients = DashMap::<String, Client>;
+    arc.type ZipChecksums = DashMap::<u64, String>;
+    arc.type ChunkedUploads = DashMap::<String, ChunkedUpload>;
+    arc.type ResumableUploads = DashMap::<PathBuf, usize>;
+    arc.type PartialWrites = DashSet::<PathBuf>;
+    arc.type AppendLocks = DashMap::<PathBuf, Arc::<TokioMutex::<()>>>;
 }
 
-pub struct File {
-    pub size: usize,
-    pub name: String,
-    pub bytes: Vec::<u8>
+pub struct UploadError {
+    pub status: StatusCode,
+    pub msg: Cow::<'static, str>
 }
 
-impl File {
-    async fn from_multipart(multipart: &mut Multipart, clients: AtomicClients, pp: AtomicProgressPinger) -> Result::<File, &'static str> {
-        let mut size = None;
-        let mut bytes = Vec::new();
-        let mut name = String::new();
-        while let Some(Ok(field)) = multipart.next().await {
-            if field.name() == "size" {
-                println!("[INFO] processing `size` field...");
-
-                let buf = field.try_fold(String::new(), |mut acc, chunk| async move {
-                    acc.push_str(std::str::from_utf8(&chunk).unwrap());
-                    Ok(acc)
-                }).await.map_err(|_| "error reading size field")?;
-
-                size = buf.parse::<usize>().ok();
-                if size.is_none() {
-                    println!("[FATAL] invalid size field: {buf}");
-                    return Err("invalid size field")
-                }
-
-                let size = unsafe { size.unwrap_unchecked() };
-                let u8_reserve = size / std::mem::size_of::<u8>();
-                if bytes.try_reserve_exact(size / u8_reserve).is_err() {
-                    println!("[FATAL] could not reserve memory: {u8_reserve}");
-                    return Err("could not reserve memory")
-                }
-
-                println!("[INFO] parsed file size: {size}");
-
-                if size > SIZE_LIMIT {
-                    #[cfg(feature = "dbg")] println!("file size exceeds limit, returning bad request..");
-                    return Err("file size exceeds limit, returning bad request..")
-                }
-            } else {
-                println!("[INFO] processing `file` field...");
+impl UploadError {
+    #[inline(always)]
+    fn response(&self) -> HttpResponse {
+        HttpResponse::build(self.status).body(self.msg.to_string())
+    }
+}
 
-                let Some(size) = size else {
-                    println!("`size` field must go first, not the `file` one");
-                    return Err("`size` field must go first, not the `file` one")
-                };
+impl From::<&'static str> for UploadError {
+    #[inline(always)]
+    fn from(msg: &'static str) -> Self {
+        Self { status: StatusCode::BAD_REQUEST, msg: msg.into() }
+    }
+}
 
-                match field.content_disposition().get_filename() {
-                    Some(name_) => name = name_.to_owned(),
-                    _ => return Err("`file` field does not have a filename")
-                }
+impl From::<String> for UploadError {
+    #[inline(always)]
+    fn from(msg: String) -> Self {
+        Self { status: StatusCode::BAD_REQUEST, msg: msg.into() }
+    }
+}
 
-                println!("[INFO {name}] size: {size}");
+// A full disk is the client's to hear about, it may send something smaller, the rest is on us.
+#[inline]
+fn write_error_status(e: &std::io::Error) -> StatusCode {
+    if e.kind() == std::io::ErrorKind::StorageFull {
+        StatusCode::INSUFFICIENT_STORAGE
+    } else {
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+}
 
-                bytes = field.try_fold((bytes, &name, &clients, &pp), |(mut bytes, name, clients, pp), chunk| async move {
-                    bytes.extend_from_slice(&chunk);
-                    let progress = (bytes.len() * 100 / size).min(100) as u8;
-                    if progress % 5 == 0 {
-                        let Some(mut ps) = clients.get_mut(name) else {
-                            println!("[ERROR] no: {name} in the clients hashmap, returning an error..");
-                            return Err(MultipartError::Incomplete)
-                        };
+#[inline]
+fn write_error(e: &std::io::Error, msg: &'static str) -> UploadError {
+    UploadError { status: write_error_status(e), msg: msg.into() }
+}
 
-                        ps.size = size;
-                        ps.progress = progress;
+#[inline]
+fn is_read_only(e: &std::io::Error) -> bool {
+    matches!(e.kind(), std::io::ErrorKind::PermissionDenied | std::io::ErrorKind::ReadOnlyFilesystem)
+}
 
-                        if let Err(e) = ps.sender.send(progress) {
-                            eprintln!("[ERROR] failed to send progress: {e}");
-                        }
+// Creates and removes a file in `dir`, so that a read-only downloads directory shows up at startup, not with the first upload.
+fn check_writable(dir: &std::path::Path) -> std::io::Result::<()> {
+    let probe = dir.join(format!(".droppa-probe-{pid}", pid = std::process::id()));
+    fs::File::create_new(&probe)?;
+    fs::remove_file(&probe)
+}
 
-                        if let Ok(pp) = pp.try_lock() {
-                            if let Some(pp) = pp.as_ref() {
-                                _ = pp.try_send(()).ok()
-                            }
-                        }
-                    }
-                    Ok((bytes, name, clients, pp))
-                }).await.map_err(|_| "error reading file field")?.0;
-            }
+// Better to turn an upload away than to leave a truncated file behind once the disk fills up, so every upload
+// that's written to `dir` checks before the first byte of it is. If the filesystem can't tell, we just try,
+// a failed write still cleans up after itself.
+fn check_free_space(dir: &std::path::Path, needed: u64) -> Result::<(), (StatusCode, String)> {
+    match fs4::available_space(dir) {
+        Ok(available) if available < needed => Err((
+            StatusCode::INSUFFICIENT_STORAGE,
+            format!("not enough space in: {dir}: {needed} bytes needed, only {available} available", dir = dir.display())
+        )),
+        Ok(..) => Ok(()),
+        Err(e) => {
+            log::warn!("could not check free space in: {dir}: {e}", dir = dir.display());
+            Ok(())
         }
-
-        Ok(File { bytes, name, size: unsafe { size.unwrap_unchecked() } })
     }
 }
 
-#[repr(u8)]
-#[derive(Copy, Clone)]
-enum Transmission { Mobile, Zipping, Desktop }
-
-struct Server {
-    qr_bytes: web::Bytes,
+// Shared by the uploads and `/upload-check`, so that both turn the same files away for the same reasons.
+fn check_size_limit(size: usize, size_limit: usize) -> Result::<(), UploadError> {
+    if size > size_limit {
+        return Err(UploadError {
+            status: StatusCode::PAYLOAD_TOO_LARGE,
+            msg: format!("file size exceeds limit of {limit}", limit = format_size(size_limit)).into()
+        })
+    }
 
-    downloads_dir: PathBuf,
+    Ok(())
+}
 
-    files: AtomicFiles,
-    clients: AtomicClients,
+#[inline]
+fn not_enough_memory(name: &str) -> UploadError {
+    UploadError {
+        status: StatusCode::INSUFFICIENT_STORAGE,
+        msg: format!("not enough memory left for: {name}, delete some files or try again later").into()
+    }
+}
 
-    files_progress_pinger: AtomicProgressPinger,
+// Deletes the temporary file once the last `File` pointing at it is gone.
+pub struct SpilledFile {
+    pub path: PathBuf
+}
 
-    zipping_progress_sender: AtomicSyncProgressSender,
+impl Drop for SpilledFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            log::error!("could not remove temporary file: {path}: {e}", path = self.path.display())
+        }
+    }
+}
 
-    zipping_progress_streamer: AtomicProgressStreamer,
-    mobile_files_progress_streamer: AtomicProgressStreamer,
-    desktop_files_progress_streamer: AtomicProgressStreamer
+#[derive(Clone)]
+pub enum FileData {
+    Memory(web::Bytes),
+    Disk(Arc::<SpilledFile>),
+    // Already in its final place in `downloads_dir`, e.g. a resumed upload
+    Saved(PathBuf)
 }
 
-impl Server {
-    #[inline(always)]
-    fn lock_streamer(&self, transmission: Transmission) -> impl Future::<Output = TokioMutexGuard::<ProgressStreamer>> {
-        use Transmission::*;
-        match transmission {
-            Mobile  => self.mobile_files_progress_streamer.lock(),
-            Zipping => self.zipping_progress_streamer.lock(),
-            Desktop => self.desktop_files_progress_streamer.lock()
+impl FileData {
+    // Blocking if the data is on disk.
+    pub fn read(&self) -> std::io::Result::<web::Bytes> {
+        match self {
+            FileData::Memory(bytes) => Ok(web::Bytes::clone(bytes)),
+            FileData::Disk(spilled) => fs::read(&spilled.path).map(Into::into),
+            FileData::Saved(path) => fs::read(path).map(Into::into)
         }
     }
 
-    #[inline(always)]
-    async fn streamer_send(&self, json: String, transmission: Transmission) {
-        if let Err(e) = self.lock_streamer(transmission).await.as_ref().expect("SENDER IS NOT INITIALIZED").send(json) {
-            eprintln!("[FATAL] could not send JSON: {e}")
+    // Blocking if the data is on disk, streams it instead of reading it all up front.
+    pub fn copy_to(&self, writer: &mut impl Write) -> std::io::Result::<()> {
+        match self {
+            FileData::Memory(bytes) => writer.write_all(bytes),
+            FileData::Disk(spilled) => std::io::copy(&mut fs::File::open(&spilled.path)?, writer).map(|_| ()),
+            FileData::Saved(path) => std::io::copy(&mut fs::File::open(path)?, writer).map(|_| ())
         }
     }
 
-    lock_fn! { files }
+    #[inline]
+    pub fn path(&self) -> Option::<&std::path::Path> {
+        match self {
+            FileData::Memory(..) => None,
+            FileData::Disk(spilled) => Some(&spilled.path),
+            FileData::Saved(path) => Some(path)
+        }
+    }
 }
 
-#[inline]
-fn user_agent_is_mobile(user_agent: &str) -> bool {
-    [
-        "Mobile",        // General mobile indicator
-        "Android",       // Android devices
-        "iPhone",        // iPhones
-        "iPod",          // iPods
-        "BlackBerry",    // BlackBerry devices
-        "Windows Phone", // Windows Phones
-        "Opera Mini",    // Opera Mini browser
-        "IEMobile",      // Internet Explorer Mobile
-    ].iter().any(|keyword| user_agent.contains(keyword))
+#[derive(Clone)]
+pub struct File {
+    pub size: usize,
+    pub name: String,
+    pub data: FileData,
+    pub label: Option::<String>,
+    // Hex SHA-256 of the data, computed while it streams in
+    pub sha256: String,
+    // Held for as long as the data sits in RAM, shared by the clones, see `MemoryBudget`
+    pub claim: Option::<Arc::<MemoryClaim>>,
+    // When it was received, what zips get unless it's on disk, then it's its mtime
+    pub modified: SystemTime,
+    // Folder it was in, relative to the one that was dropped, e.g. `photos/2023`, recreated in zips and on disk
+    pub dir: Option::<String>,
+    // What `sniff` made of its first bytes, if anything
+    pub mime: Option::<&'static str>
 }
 
-#[get("/progress/{file_name}")]
-async fn track_progress(rq: HttpRequest, path: Path::<String>, state: Data::<Server>) -> impl Responder {
-    let Some(user_agent) = rq.headers().get("User-Agent").and_then(|header| header.to_str().ok()) else {
-        return HttpResponse::BadRequest().body("Request to `/` that does not contain user agent")
-    };
-
-    let file_name = path.into_inner();
-    println!("[INFO] client connected to <http://localhost:{PORT}/progress/{file_name}>");
+// Caps how much desktop uploads may keep in RAM altogether, `DROPPA_MEM_BUDGET`.
+pub struct MemoryBudget {
+    limit: usize,
+    used: Arc::<AtomicUsize>
+}
 
-    let tx = watch::channel(0).0;
-    let rx = WatchStream::new(tx.subscribe());
+impl MemoryBudget {
+    #[inline(always)]
+    fn new(limit: usize) -> Self {
+        Self { limit, used: Arc::new(AtomicUsize::new(0)) }
+    }
 
-    println!("[INFO] inserted: {file_name} into the clients hashmap");
-    state.clients.insert(file_name, Client {
-        sender: tx,
-        progress: 0,
-        size: 0,
-        mobile: user_agent_is_mobile(user_agent)
-    });
+    // `None` if `size` more bytes don't fit
+    fn claim(&self, size: usize) -> Option::<MemoryClaim> {
+        self.used.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+            used.checked_add(size).filter(|&used| used <= self.limit)
+        }).ok()?;
 
-    HttpResponse::Ok()
-        .append_header(("Content-Type", "text/event-stream"))
-        .append_header(("Cache-Control", "no-cache"))
-        .append_header(("Connection", "keep-alive"))
-        .streaming(rx.map(|data| {
-            Ok::<_, actix_web::Error>(format!("data: {{ \"progress\": {data} }}\n\n").into())
-        }))
-}
+        Some(MemoryClaim { used: Arc::clone(&self.used), limit: self.limit, size })
+    }
 
-#[get("/")]
-async fn index(rq: HttpRequest) -> impl Responder {
-    let Some(user_agent) = rq.headers().get("User-Agent").and_then(|header| header.to_str().ok()) else {
-        return HttpResponse::BadRequest().body("Request to `/` that does not contain user agent")
-    };
+    #[inline(always)]
+    fn used(&self) -> usize {
+        self.used.load(Ordering::SeqCst)
+    }
 
-    HttpResponse::Ok()
-        .append_header(("Content-Type", "text/html"))
-        .body(if user_agent_is_mobile(user_agent) {HOME_MOBILE_HTML} else {HOME_DESKTOP_HTML})
+    // Like `claim`, but without holding on to anything, the bytes may be gone by the time they're claimed
+    #[inline]
+    fn fits(&self, size: usize) -> bool {
+        self.used().checked_add(size).is_some_and(|used| used <= self.limit)
+    }
 }
 
-#[get("/qr.png")]
-async fn qr_code(state: Data::<Server>) -> impl Responder {
-    HttpResponse::Ok()
-        .content_type("image/png")
-        .body(web::Bytes::clone(&state.qr_bytes))
+// Gives its bytes back to the budget when dropped, i.e. when the upload fails, or the file is deleted or cleared.
+pub struct MemoryClaim {
+    used: Arc::<AtomicUsize>,
+    limit: usize,
+    size: usize
 }
 
-#[post("/upload-desktop")]
-async fn upload_desktop(mut multipart: Multipart, state: Data::<Server>) -> impl Responder {
-    println!("[INFO] upload-desktop requested, parsing multipart..");
-
-    let file = match File::from_multipart(&mut multipart, Arc::clone(&state.clients), Arc::clone(&state.files_progress_pinger)).await {
-        Ok(f) => f,
-        Err(e) => return HttpResponse::BadRequest().body(e)
-    };
+impl MemoryClaim {
+    // Claims what's past the size it was made with, `false` if that doesn't fit, the claim stays as it was then.
+    fn grow_to(&mut self, size: usize) -> bool {
+        let Some(more) = size.checked_sub(self.size).filter(|more| *more > 0) else {
+            return true
+        };
 
-    println!("[INFO] uploaded: {name}", name = file.name);
+        let grown = self.used.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+            used.checked_add(more).filter(|&used| used <= self.limit)
+        });
 
-    {
-        state.lock_files().push(file);
+        if grown.is_ok() {
+            self.size = size
+        }
+        grown.is_ok()
     }
+}
 
-    HttpResponse::Ok().finish()
+impl Drop for MemoryClaim {
+    #[inline(always)]
+    fn drop(&mut self) {
+        self.used.fetch_sub(self.size, Ordering::SeqCst);
+    }
 }
 
-#[post("/upload-mobile")]
-async fn upload_mobile(mut multipart: Multipart, state: Data::<Server>) -> impl Responder {
-    println!("[INFO] upload-mobile requested, parsing multipart..");
+// Where `File::from_multipart` should put the file.
+#[derive(Copy, Clone)]
+enum Destination<'a> {
+    // Counted against the budget if there's one
+    Memory(Option::<&'a MemoryBudget>),
+    // `--spill-to-disk`
+    Spill(&'a std::path::Path),
+    // Resumable upload, appended to `<dir>/[label/]<name>.part` from `offset` on and renamed once complete.
+    // `totals` remembers the size each partial file was started with.
+    Resume {
+        dir: &'a std::path::Path,
+        offset: u64,
+        totals: &'a DashMap::<PathBuf, usize>
+    }
+}
 
-    let File { bytes, name, size } = match File::from_multipart(&mut multipart, Arc::clone(&state.clients), Arc::clone(&state.files_progress_pinger)).await {
-        Ok(f) => f,
-        Err(e) => return HttpResponse::BadRequest().body(e)
-    };
+// Where `File::from_multipart` puts the bytes as they arrive.
+enum Sink {
+    Memory(Vec::<u8>),
+    Disk {
+        file: tokio::fs::File,
+        spilled: SpilledFile,
+        written: usize
+    },
+    // Unlike `Disk`, the file is kept if the upload breaks off, that's the whole point
+    Resume {
+        file: tokio::fs::File,
+        path: PathBuf,
+        written: usize
+    }
+}
 
-    #[cfg(feature = "dbg")] let mut name = name;
-    #[cfg(feature = "dbg")] { name = name + ".test" }
+impl Sink {
+    async fn spill(dir: &std::path::Path) -> std::io::Result::<Sink> {
+        static SPILL_COUNTER: AtomicUsize = AtomicUsize::new(0);
 
-    if let Err(e) = actix_rt::task::spawn_blocking(move || {
-        let file_path = format!{
-            "{downloads}{DELIM}{name}",
-            downloads = state.downloads_dir.display()
-        };
+        let path = dir.join(format!("{n}.part", n = SPILL_COUNTER.fetch_add(1, Ordering::Relaxed)));
+        let file = tokio::fs::File::create(&path).await?;
+        Ok(Sink::Disk { file, spilled: SpilledFile { path }, written: 0 })
+    }
 
-        let file = match fs::File::create(&file_path) {
-            Ok(f) => f,
-            Err(e) => return Err(format!("could not create file: {name}: {e}"))
+    async fn resume(path: PathBuf, offset: u64) -> Result::<Sink, UploadError> {
+        let io_error = |e: std::io::Error| {
+            log::error!("could not open: {path}: {e}", path = path.display());
+            write_error(&e, "could not open partial file")
         };
 
-        println!("[INFO] copying bytes to: {file_path}..");
-
-        let mut wbuf = BufWriter::with_capacity(size, file);
-        if let Err(e) = wbuf.write_all(&bytes) {
-            return Err(format!("could not copy bytes: {name}: {e}"))
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await.map_err(io_error)?
         }
 
-        println!("[INFO] uploaded: {name}");
+        let mut file = tokio::fs::OpenOptions::new().write(true).create(true).truncate(false).open(&path).await.map_err(io_error)?;
+        let len = file.metadata().await.map_err(io_error)?.len();
+        if offset > len {
+            return Err(UploadError {
+                status: StatusCode::RANGE_NOT_SATISFIABLE,
+                msg: format!("offset: {offset} is past the {len} bytes received so far").into()
+            })
+        }
 
-        Ok(())
-    }).await {
-        return HttpResponse::SeeOther().body(format!("error copying bytes: {e}"))
+        // Whatever is past `offset` the client doesn't know we got, it's about to send it again
+        file.set_len(offset).await.map_err(io_error)?;
+        file.seek(SeekFrom::Start(offset)).await.map_err(io_error)?;
+        Ok(Sink::Resume { file, path, written: offset as usize })
     }
 
-    HttpResponse::Ok().finish()
-}
+    // Throws away what was received, `Memory` and `Disk` take care of that by themselves when dropped.
+    async fn discard(&mut self) {
+        if let Sink::Resume { path, .. } = self {
+            if let Err(e) = tokio::fs::remove_file(&path).await {
+                log::error!("could not remove: {path}: {e}", path = path.display())
+            }
+        }
+    }
 
-struct ProgressTracker<W: Write> {
-    writer: W,
-    written: usize,
-    total_size: usize,
-    progress_sender: AtomicSyncProgressSender
-}
+    // The digest has to cover what was received before the upload got resumed too.
+    async fn hasher(&self) -> std::io::Result::<Sha256> {
+        let mut hasher = Sha256::new();
+        if let Sink::Resume { path, written, .. } = self {
+            let mut prefix = tokio::fs::File::open(path).await?.take(*written as u64);
+            let mut buf = vec![0; 64 * 1024];
+            loop {
+                let n = prefix.read(&mut buf).await?;
+                if n == 0 { break }
+                hasher.update(&buf[..n])
+            }
+        }
+        Ok(hasher)
+    }
 
-impl<W: Write> ProgressTracker::<W> {
-    #[inline(always)]
-    pub fn new(writer: W, total_size: usize, progress_sender: AtomicSyncProgressSender) -> Self {
-        Self { writer, written: 0, total_size, progress_sender }
+    async fn write(&mut self, chunk: &[u8]) -> std::io::Result::<()> {
+        match self {
+            Sink::Memory(bytes) => {
+                bytes.extend_from_slice(chunk);
+                Ok(())
+            }
+            Sink::Disk { file, written, .. } | Sink::Resume { file, written, .. } => {
+                file.write_all(chunk).await?;
+                *written += chunk.len();
+                Ok(())
+            }
+        }
     }
 
     #[inline]
-    pub fn progress(&self) -> usize {
-        if self.written >= self.total_size {
-            100
-        } else {
-            (self.written * 100 / self.total_size).min(100)
+    fn len(&self) -> usize {
+        match self {
+            Sink::Memory(bytes) => bytes.len(),
+            Sink::Disk { written, .. } | Sink::Resume { written, .. } => *written
         }
     }
-}
-
-impl<W: Write> Write for ProgressTracker::<W> {
-    fn write(&mut self, buf: &[u8]) -> std::io::Result::<usize> {
-        let written_ = self.writer.write(buf)?;
-        self.written += written_;
 
-        let p = self.progress();
-        if p % 5 == 0 {
-            let progress_sender = self.progress_sender.lock().unwrap();
-            progress_sender.as_ref().map(|ps| ps.try_send(p as _));
+    async fn finish(self) -> std::io::Result::<FileData> {
+        match self {
+            Sink::Memory(bytes) => Ok(FileData::Memory(bytes.into())),
+            Sink::Disk { mut file, spilled, .. } => {
+                file.flush().await?;
+                Ok(FileData::Disk(Arc::new(spilled)))
+            }
+            Sink::Resume { mut file, path, .. } => {
+                file.flush().await?;
+                let done = free_path(&path.with_extension(""));
+                tokio::fs::rename(&path, &done).await?;
+                Ok(FileData::Saved(done))
+            }
         }
-
-        Ok(written_)
     }
+}
 
-    #[inline(always)]
-    fn flush(&mut self) -> std::io::Result<()> {
-        self.writer.flush()
+// Reads the rest of the body, otherwise the client is most likely still sending when
+// we respond, the connection gets reset, and it never gets to see the status.
+async fn drain(multipart: &mut Multipart) {
+    while let Some(Ok(mut field)) = multipart.next().await {
+        while let Some(Ok(_)) = field.next().await {}
     }
 }
 
-#[get("/download-files-mobile")]
-async fn download_files(state: Data::<Server>) -> impl Responder {
-    println!("[INFO] download files requested, zipping them up..");
-
-    let files = Arc::clone(&state.files);
-    let Ok(Ok(zip_bytes)) = actix_rt::task::spawn_blocking(move || {
-        let (size, len) = {
-            let files = files.lock().unwrap();
-            let size = files.iter().map(|f| f.size).sum::<usize>();
-            (size, files.len())
-        };
+// Same as `drain`, for the endpoints that take the raw body.
+async fn drain_payload(body: &mut web::Payload) {
+    while let Some(Ok(_)) = body.next().await {}
+}
 
-        let mut zip_bytes = Cursor::new(Vec::with_capacity(size));
+// Parses sizes like `1048576`, `500M`, `2G` or `1.5GiB`, suffixes are binary and case-insensitive.
+fn parse_size(s: &str) -> Option::<usize> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(s.len());
+    let (number, suffix) = s.split_at(split);
 
-        {
-            let mut opts = SimpleFileOptions::default()
-                .compression_level(Some(8))
-                .compression_method(CompressionMethod::Deflated);
+    let unit = match suffix.trim().to_ascii_uppercase().trim_end_matches("IB").trim_end_matches('B') {
+        ""  => 1,
+        "K" => 1024,
+        "M" => 1024 * 1024,
+        "G" => GIG,
+        "T" => GIG * 1024,
+        _ => return None
+    };
 
-            if size > const { GIG * 4 } || len > 65536 {
-                opts = opts.large_file(true)
-            }
+    let number = number.parse::<f64>().ok()?;
+    let size = (number * unit as f64) as usize;
+    (number.is_finite() && size > 0).then_some(size)
+}
 
-            let mut zip = ProgressTracker::new(ZipWriter::new(&mut zip_bytes), size, Arc::clone(&state.zipping_progress_sender));
-            {
-                let files = files.lock().unwrap();
-                for File { name, bytes, .. } in files.iter() {
-                    zip.writer.start_file(&name, opts)?;
-                    zip.write_all(&bytes)?
-                }
-            }
+fn format_size(size: usize) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
 
-            zip.writer.finish().map_err(|e| {
-                std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string())
-            })?;
-        }
+    let mut size = size as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1
+    }
 
-        Ok::<_, std::io::Error>(zip_bytes.into_inner())
-    }).await else {
-        return HttpResponse::SeeOther().body("error zipping up your files")
-    };
+    if size.fract() == 0.0 {
+        format!("{size} {unit}", unit = UNITS[unit])
+    } else {
+        format!("{size:.2} {unit}", unit = UNITS[unit])
+    }
+}
 
-    println!("[INFO] finished zipping up the files, sending to your phone..");
-    HttpResponse::Ok()
-        .content_type("application/zip")
-        .body(zip_bytes)
+// Magic numbers of what phones usually send. MP4 and HEIC are both ISO media files, told apart by their brand.
+fn sniff(head: &[u8]) -> Option::<(&'static str, &'static str)> {
+    match head {
+        [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n', ..] => Some(("image/png", "png")),
+        [0xff, 0xd8, 0xff, ..] => Some(("image/jpeg", "jpg")),
+        [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => Some(("image/gif", "gif")),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some(("image/webp", "webp")),
+        [b'%', b'P', b'D', b'F', b'-', ..] => Some(("application/pdf", "pdf")),
+        [b'P', b'K', 0x03, 0x04, ..] => Some(("application/zip", "zip")),
+        [_, _, _, _, b'f', b't', b'y', b'p', brand @ ..] => match brand {
+            b"heic" | b"heix" | b"mif1" => Some(("image/heic", "heic")),
+            b"qt  " => Some(("video/quicktime", "mov")),
+            _ => Some(("video/mp4", "mp4"))
+        },
+        _ => None
+    }
 }
 
-//////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
-// I could've used the `FnOnce` and `FnMut` traits here and called different async closures do to different things, //
-// but it seems that this feature is really, really underdeveloped yet.                                             //
+// Not at 0, `.bashrc` has no extension, like in `numbered_name`.
+#[inline]
+fn has_extension(name: &str) -> bool {
+    name.get(1..).is_some_and(|rest| rest.contains('.'))
+}
+
+async fn read_head(path: &std::path::Path) -> std::io::Result::<Vec::<u8>> {
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    tokio::fs::File::open(path).await?.take(SNIFF_LEN as u64).read_to_end(&mut head).await?;
+    Ok(head)
+}
+
+// What `sniff` looks at, wherever the data ended up.
+async fn head_of(data: &FileData) -> std::io::Result::<Vec::<u8>> {
+    match data {
+        FileData::Memory(bytes) => Ok(bytes[..bytes.len().min(SNIFF_LEN)].to_vec()),
+        FileData::Disk(spilled) => read_head(&spilled.path).await,
+        FileData::Saved(path) => read_head(path).await
+    }
+}
+
+// Browsers only ever send a basename, anything else is somebody trying to write outside of `downloads_dir`.
+// Keeps the last component, whichever separator it uses, and drops the characters Windows won't take in a name.
+fn sanitize_file_name(requested: &str) -> Result::<String, String> {
+    let name = requested.rsplit(['/', '\\']).next().unwrap_or_default();
+    let name = name.chars()
+        .filter(|c| !c.is_control() && !matches!(c, ':' | '*' | '?' | '"' | '<' | '>' | '|'))
+        .collect::<String>();
+
+    let name = name.trim();
+    if matches!(name, "" | "." | "..") {
+        return Err(format!("invalid filename: {requested:?}"))
+    }
+
+    // The filesystem would refuse it with some obscure error, and only once the whole thing was received
+    if name.len() > MAX_FILE_NAME_LEN {
+        return Err(format!("filename is too long: {len} bytes, at most {MAX_FILE_NAME_LEN} are allowed", len = name.len()))
+    }
+
+    Ok(name.to_owned())
+}
+
+// Where a file of `label` from folder `dir` goes in `downloads_dir`, both being sanitized already.
+fn target_dir(downloads_dir: &std::path::Path, label: Option::<&str>, dir: Option::<&str>) -> PathBuf {
+    let mut path = downloads_dir.to_owned();
+    path.extend(label);
+    path.extend(dir);
+    path
+}
+
+// Entries that would land outside of `dest`, with `..` or an absolute path, fail the whole thing, so does unpacking more than `size_limit`.
+fn unzip_into(zip: &mut zip::ZipArchive::<fs::File>, dest: &std::path::Path, size_limit: usize) -> Result::<usize, String> {
+    let mut total = 0;
+    let mut count = 0;
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i).map_err(|e| format!("could not read entry #{i}: {e}"))?;
+        let Some(relative) = entry.enclosed_name() else {
+            return Err(format!("entry: {name} points outside of the archive", name = entry.name()))
+        };
+
+        let path = dest.join(relative);
+        if entry.is_dir() {
+            fs::create_dir_all(&path).map_err(|e| format!("could not create directory: {path}: {e}", path = path.display()))?;
+            continue
+        }
+
+        // Only turns away the honest archives early, the header can claim any size
+        if total + entry.size() > size_limit as u64 {
+            return Err(format!("archive unpacks to more than {size_limit} bytes"))
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("could not create directory: {parent}: {e}", parent = parent.display()))?;
+        }
+
+        // What's actually written is what counts, one byte past the limit is enough to tell
+        let remaining = size_limit as u64 - total;
+        let mut out = fs::File::create_new(&path).map_err(|e| format!("could not create: {path}: {e}", path = path.display()))?;
+        total += std::io::copy(&mut (&mut entry).take(remaining + 1), &mut out).map_err(|e| format!("could not extract: {path}: {e}", path = path.display()))?;
+        if total > size_limit as u64 {
+            return Err(format!("archive unpacks to more than {size_limit} bytes"))
+        }
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+// `a.txt`, `a (1).txt`, `a (2).txt`.. what browsers do when a download already exists.
+fn numbered_name(name: &str, n: usize) -> String {
+    if n == 0 {
+        return name.to_owned()
+    }
+
+    // Not at 0, `.bashrc` is all stem
+    match name.get(1..).and_then(|rest| rest.find('.')).map(|dot| dot + 1) {
+        Some(dot) => format!("{stem} ({n}){ext}", stem = &name[..dot], ext = &name[dot..]),
+        None => format!("{name} ({n})")
+    }
+}
+
+// First `numbered_name` of `path` that doesn't exist yet.
+fn free_path(path: &std::path::Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    (0..).map(|n| path.with_file_name(numbered_name(&name, n))).find(|path| !path.exists()).unwrap()
+}
+
+#[inline]
+fn content_length(rq: &HttpRequest) -> Option::<usize> {
+    rq.headers().get("Content-Length")?.to_str().ok()?.parse().ok()
+}
+
+// One `key=value` line per received file, under its own target so that `RUST_LOG=droppa::access=info` picks just these.
+// Files of the same request share its duration, so the throughput is the request's one.
+fn log_transfers(endpoint: &str, files: &[FileEntry], started: Instant, client: Option::<IpAddr>) {
+    let duration = started.elapsed();
+    let total = files.iter().map(|file| file.size).sum::<usize>();
+    let bps = (total as f64 / duration.as_secs_f64().max(0.001)) as u64;
+    let client = client.map_or_else(|| "-".to_owned(), |ip| ip.to_string());
+
+    for file in files {
+        log::info!(
+            target: "droppa::access",
+            "endpoint={endpoint} name={name:?} size={size} duration_ms={ms} bps={bps} client={client}",
+            name = file.name, size = file.size, ms = duration.as_millis()
+        )
+    }
+}
+
+// `photos/2023` out of whatever relative path the client sent, `Ok(None)` for no folder at all.
+// `..` is refused rather than dropped, there's no telling where the file was meant to go then.
+fn sanitize_dir(dir: &str) -> Result::<Option::<String>, String> {
+    let mut components = Vec::new();
+    for component in dir.split(['/', '\\']).map(str::trim).filter(|c| !matches!(*c, "" | ".")) {
+        match sanitize_file_name(component) {
+            Ok(component) => components.push(component),
+            Err(e) => return Err(format!("invalid path: {dir:?}: {e}"))
+        }
+    }
+
+    Ok((!components.is_empty()).then(|| components.join("/")))
+}
+
+// Labels end up as directory names on the mobile path, so only keep the boring characters.
+fn sanitize_label(label: &str) -> Option::<String> {
+    let label = label.trim().chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+        .take(MAX_LABEL_LEN)
+        .collect::<String>()
+        .to_ascii_lowercase();
+
+    (!label.is_empty()).then_some(label)
+}
+
+impl File {
+    // Takes any number of `size`, `file` pairs, each `file` field being preceded by its own `size`.
+    // A `label` applies to every file that comes after it, a `path`, the folder the file was in, only to the next one.
+    async fn from_multipart(multipart: &mut Multipart, clients: AtomicClients, pp: AtomicProgressPinger, dest: Destination<'_>, content_length: Option::<usize>, config: &Config, throttle: Option::<Arc::<Throttle>>) -> Result::<Vec::<File>, UploadError> {
+        let (size_limit, progress_step) = (config.size_limit, config.progress_step);
+        let throttle = throttle.as_deref();
+        let mut size = None;
+        let mut label = None;
+        let mut dir = None;
+        let mut files = Vec::<File>::new();
+        while let Some(Ok(field)) = multipart.next().await {
+            if field.name() == "label" {
+                let buf = field.try_fold(String::new(), |mut acc, chunk| async move {
+                    acc.push_str(&String::from_utf8_lossy(&chunk));
+                    Ok(acc)
+                }).await.map_err(|_| "error reading label field")?;
+
+                label = sanitize_label(&buf);
+                log::info!("parsed label: {label:?}");
+            } else if field.name() == "path" {
+                let buf = field.try_fold(String::new(), |mut acc, chunk| async move {
+                    acc.push_str(&String::from_utf8_lossy(&chunk));
+                    Ok(acc)
+                }).await.map_err(|_| "error reading path field")?;
+
+                dir = sanitize_dir(&buf)?;
+                log::info!("parsed path: {dir:?}");
+            } else if field.name() == "size" {
+                log::info!("processing `size` field...");
+
+                let buf = field.try_fold(String::new(), |mut acc, chunk| async move {
+                    acc.push_str(&String::from_utf8_lossy(&chunk));
+                    Ok(acc)
+                }).await.map_err(|_| "error reading size field")?;
+
+                let Ok(parsed) = buf.parse::<usize>() else {
+                    log::error!("invalid size field: {buf}");
+                    return Err("invalid size field".into())
+                };
+
+                size = Some(parsed);
+                if let Err(e) = check_size_limit(parsed, size_limit) {
+                    #[cfg(feature = "dbg")] log::debug!("file size exceeds limit, returning payload too large..");
+
+                    drain(multipart).await;
+                    return Err(e)
+                }
+
+                log::info!("parsed file size: {parsed}");
+            } else {
+                log::info!("processing `file` field...");
+
+                // Plain HTML forms and `curl -F` send no `size` field, then what's left of the request's Content-Length is the best guess.
+                // It's too big unless it's the last file, but it's a bound. Without either, progress stays at 0 until it's done.
+                let declared = size.take();
+                let size = declared.or_else(|| content_length.map(|length| length.saturating_sub(files.iter().map(|file| file.size).sum::<usize>())));
+
+                // The offset only means something for the one file
+                if matches!(dest, Destination::Resume { .. }) && !files.is_empty() {
+                    return Err("resumable uploads take one file per request".into())
+                }
+
+                // Progress is reported under the name the client knows, not the sanitized one
+                let key = match field.content_disposition().get_filename() {
+                    Some(name_) => name_.to_owned(),
+                    _ => return Err("`file` field does not have a filename".into())
+                };
+
+                let mut name = match sanitize_file_name(&key) {
+                    Ok(name_) => name_,
+                    Err(e) => return Err(e.into())
+                };
+
+                let dir = dir.take();
+
+                log::info!("{name}: size: {size:?}");
+
+                let mut claim = None;
+                let sink = match dest {
+                    Destination::Resume { dir: downloads_dir, offset, totals } => {
+                        let Some(size) = declared else {
+                            return Err("resumable uploads need a `size` field".into())
+                        };
+
+                        let path = target_dir(downloads_dir, label.as_deref(), dir.as_deref()).join(format!("{name}.part"));
+
+                        // The partial file is gone (cancelled, or removed by hand), so is the size it was started with
+                        if !tokio::fs::try_exists(&path).await.unwrap_or(false) {
+                            totals.insert(path.clone(), size);
+                        }
+
+                        let total = *totals.entry(path.clone()).or_insert(size);
+                        if total != size {
+                            return Err(UploadError {
+                                status: StatusCode::CONFLICT,
+                                msg: format!("{name} was started with size: {total}, not: {size}").into()
+                            })
+                        }
+
+                        let needed = (size as u64).saturating_sub(offset);
+                        check_free_space(downloads_dir, needed).map_err(|(status, msg)| UploadError { status, msg: msg.into() })?;
+
+                        Sink::resume(path, offset).await?
+                    }
+                    Destination::Spill(dir) => {
+                        // Without a size there's nothing to check against, the writes still fail cleanly
+                        if let Some(size) = size {
+                            check_free_space(dir, size as u64).map_err(|(status, msg)| UploadError { status, msg: msg.into() })?
+                        }
+
+                        Sink::spill(dir).await.map_err(|e| {
+                            log::error!("could not create temporary file in: {dir}: {e}", dir = dir.display());
+                            write_error(&e, "could not create temporary file")
+                        })?
+                    }
+                    Destination::Memory(budget) => {
+                        if let Some(budget) = budget {
+                            let Some(size) = size else {
+                                return Err(UploadError {
+                                    status: StatusCode::LENGTH_REQUIRED,
+                                    msg: "uploads kept in memory need a `size` field or a Content-Length".into()
+                                })
+                            };
+
+                            let Some(claimed) = budget.claim(size) else {
+                                log::info!("not enough memory left for: {name}, {used} of {limit} in use", used = format_size(budget.used()), limit = format_size(budget.limit));
+                                drop(field);
+                                drain(multipart).await;
+                                return Err(not_enough_memory(&name))
+                            };
+                            claim = Some(claimed)
+                        }
+
+                        // The whole file ends up in there, so get it all at once instead of growing it chunk by chunk
+                        let mut bytes = Vec::new();
+                        let reserve = size.unwrap_or_default();
+                        if bytes.try_reserve_exact(reserve).is_err() {
+                            log::error!("could not reserve memory: {reserve}");
+                            return Err(UploadError { status: StatusCode::INSUFFICIENT_STORAGE, msg: "could not reserve memory".into() })
+                        }
+                        Sink::Memory(bytes)
+                    }
+                };
+
+                let hasher = sink.hasher().await.map_err(|e| {
+                    log::error!("could not hash what was received of: {name} so far: {e}");
+                    UploadError { status: StatusCode::INTERNAL_SERVER_ERROR, msg: "could not read partial file".into() }
+                })?;
+
+                let cancel = Arc::new(AtomicBool::new(false));
+                let (pause, paused) = watch::channel(false);
+                if let Some(mut client) = clients.get_mut(&key) {
+                    client.cancel = Some(Arc::clone(&cancel));
+                    client.pause = Some(pause);
+                    client.progress = 0;
+                    client.last_update = None;
+                    client.rate = Rate::new(sink.len())
+                }
+
+                // Whichever way this ends, the entry goes with it, and with it the progress streams
+                let mut client_guard = ClientGuard { clients: &clients, key: &key, cancel: Arc::clone(&cancel), done: false };
+
+                let folded = field.map_err(|_| UploadError::from("error reading file field")).try_fold((sink, hasher, claim, &key, &clients, &pp, &cancel, &paused), |(mut sink, mut hasher, mut claim, name, clients, pp, cancel, paused), chunk| async move {
+                    // Not reading any further holds the client back as well, there's no timeout on that, so it can wait for as long as it takes.
+                    // Nobody can pause it if there was no entry to put `pause` in, then the sender is gone, but it's never paused either.
+                    if *paused.borrow() {
+                        _ = paused.clone().wait_for(|paused| !paused).await;
+                    }
+
+                    if cancel.load(Ordering::SeqCst) {
+                        sink.discard().await;
+                        return Err(UploadError { status: StatusCode::from_u16(499).unwrap(), msg: "upload was cancelled".into() })
+                    }
+
+                    // Only the declared size was checked up front
+                    if sink.len() + chunk.len() > size_limit {
+                        sink.discard().await;
+                        return Err(UploadError {
+                            status: StatusCode::PAYLOAD_TOO_LARGE,
+                            msg: format!("file size exceeds limit of {limit}", limit = format_size(size_limit)).into()
+                        })
+                    }
+
+                    // The size the claim was made for is only what the client said, what's sent past it has to fit as well
+                    if claim.as_mut().is_some_and(|claim| !claim.grow_to(sink.len() + chunk.len())) {
+                        log::info!("{name} is larger than the: {size:?} bytes it was claimed with, and the rest doesn't fit in memory");
+                        return Err(not_enough_memory(name))
+                    }
+
+                    sink.write(&chunk).await.map_err(|e| {
+                        log::error!("could not write: {name}: {e}");
+                        write_error(&e, "could not write file")
+                    })?;
+                    hasher.update(&chunk);
+
+                    // Not reading holds the client back, like with pausing
+                    if let Some(wait) = throttle.map(|throttle| throttle.take(chunk.len())).filter(|wait| !wait.is_zero()) {
+                        tokio_sleep(wait).await
+                    }
+
+                    let progress = size.map_or(0, |size| (sink.len() * 100 / size.max(1)).min(100) as u8);
+                    // Nobody subscribed to its progress, like with `curl`, then there's nobody to report it to
+                    let Some(mut ps) = clients.get_mut(name) else {
+                        return Ok((sink, hasher, claim, name, clients, pp, cancel, paused))
+                    };
+
+                    ps.size = size.unwrap_or(sink.len());
+                    ps.received = sink.len();
+
+                    if progress_due(ps.last_update, ps.progress, progress, progress_step) {
+                        ps.progress = progress;
+                        ps.last_update = Some(Instant::now());
+
+                        let (bps, eta_secs) = ps.rate.sample(sink.len(), size.unwrap_or(sink.len()));
+                        let eta_secs = eta_secs.filter(|_| size.is_some());
+                        // Kept even while no tab is watching, unlike with `send`, so that one that reconnects picks up from here, not from 0.
+                        // The entry is dropped when the upload is done.
+                        ps.sender.send_replace(ProgressEvent { progress, bps, eta_secs, state: Some(UploadState::Uploading) });
+                        if ps.sender.is_closed() {
+                            log::debug!("nobody is watching the progress of: {name} at the moment");
+                        }
+
+                        if let Ok(pp) = pp.try_lock() {
+                            if let Some(pp) = pp.as_ref() {
+                                _ = pp.try_send(()).ok()
+                            }
+                        }
+                    }
+                    Ok((sink, hasher, claim, name, clients, pp, cancel, paused))
+                }).await;
+
+                // Done either way, the next upload of the same name gets its own flag
+                if let Some(mut client) = clients.get_mut(&key) {
+                    client.cancel = None;
+                    client.pause = None
+                }
+
+                let (sink, hasher, claim, ..) = match folded {
+                    Ok(folded) => folded,
+                    Err(mut e) if cancel.load(Ordering::SeqCst) => {
+                        log::info!("upload of: {name} was cancelled");
+                        // nginx's "client closed request", there's no standard status for this
+                        e.status = StatusCode::from_u16(499).unwrap();
+                        return Err(e)
+                    }
+                    // Past the limit or out of memory halfway through, the client is still sending, like when that's known up front
+                    Err(e) if matches!(e.status, StatusCode::PAYLOAD_TOO_LARGE | StatusCode::INSUFFICIENT_STORAGE) => {
+                        drain(multipart).await;
+                        return Err(e)
+                    }
+                    Err(e) => return Err(e)
+                };
+
+                if let (Destination::Resume { totals, .. }, Sink::Resume { path, written, .. }) = (dest, &sink) {
+                    let size = declared.unwrap_or_default();
+                    if *written != size {
+                        return Err(format!("got: {written} of: {size} bytes of: {name}, resume from there").into())
+                    }
+                    totals.remove(path);
+                }
+
+                // What was received, the declared size may have been off, a resumed upload was checked against it above
+                let size = sink.len();
+
+                let sha256 = hex(&hasher.finalize());
+
+                let data = sink.finish().await.map_err(|e| {
+                    log::error!("could not finish writing: {name}: {e}");
+                    write_error(&e, "could not write file")
+                })?;
+
+                let sniffed = head_of(&data).await.ok().as_deref().and_then(sniff);
+
+                // Resumed uploads are renamed to a free name once complete
+                if let FileData::Saved(path) = &data {
+                    name = path.file_name().unwrap_or_default().to_string_lossy().into_owned()
+                } else if let Some((mime, ext)) = sniffed.filter(|_| !has_extension(&name)) {
+                    // Phones like to send photos named just `image` or so
+                    log::info!("{name} has no extension, looks like: {mime}, naming it: {name}.{ext}");
+                    name = format!("{name}.{ext}")
+                }
+
+                let mime = sniffed.map(|(mime, _)| mime);
+                files.push(File { data, name, label: label.clone(), sha256, size, claim: claim.map(Arc::new), modified: SystemTime::now(), dir, mime });
+                client_guard.done = true
+            }
+        }
+
+        if files.is_empty() {
+            return Err("no `file` field in the request".into())
+        }
+
+        Ok(files)
+    }
+}
+
+// Removes the progress client of an upload once it's done, unless another upload of the same name took it over meanwhile.
+struct ClientGuard<'a> {
+    clients: &'a AtomicClients,
+    key: &'a str,
+    cancel: Arc::<AtomicBool>,
+    // Set once the file is received in full
+    done: bool
+}
+
+impl Drop for ClientGuard<'_> {
+    fn drop(&mut self) {
+        let ours = |_: &String, client: &Client| client.cancel.as_ref().is_none_or(|cancel| Arc::ptr_eq(cancel, &self.cancel));
+        let Some((_, client)) = self.clients.remove_if(self.key, ours) else { return };
+
+        // Not a progress update, it's what the `done` event that closes `/progress/{file_name}` carries
+        let done = self.done;
+        client.sender.send_if_modified(|event| {
+            if !matches!(event.state, Some(UploadState::Cancelled)) {
+                event.state = Some(if done { UploadState::Done } else { UploadState::Failed })
+            }
+            false
+        });
+
+        log::info!("removed: {key} from the clients hashmap", key = self.key)
+    }
+}
+
+// Which progress stream an update goes to, named after the page that reads it:
+// - `Mobile`: `/download-files-progress-mobile`, the phone watching the desktop's uploads, that it can download
+// - `Desktop`: `/download-files-progress-desktop`, the desktop watching the phone's uploads
+// - `Zipping`: `/zipping-progress`, whoever downloads the archive watching it being zipped. The desktop sees
+//   that too, as an entry of its own stream, see `relay_zipping_progress`.
+#[repr(u8)]
+#[derive(Copy, Clone)]
+enum Transmission { Mobile, Zipping, Desktop }
+
+// Keeps a counter incremented for as long as it's alive, so we know what's still running when draining.
+struct TransferGuard(Arc::<AtomicUsize>);
+
+impl TransferGuard {
+    #[inline(always)]
+    fn new(counter: &Arc::<AtomicUsize>) -> Self {
+        counter.fetch_add(1, Ordering::SeqCst);
+        Self(Arc::clone(counter))
+    }
+}
+
+impl Drop for TransferGuard {
+    #[inline(always)]
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+struct Server {
+    // What `--flags` and `DROPPA_*` variables were resolved to at startup
+    config: Config,
+
+    listen_addr: SocketAddr,
+    started: Instant,
+    qr_bytes: AtomicQrBytes,
+    qr_ecc: QrCodeEcc,
+    // What `qr_bytes` encodes, swapped along with it
+    qr_target: AtomicQrTarget,
+    // `--wifi-ssid`, the network doesn't change under us like the address may, so it's made once
+    wifi_qr_bytes: Option::<web::Bytes>,
+
+    tls_fingerprint: Option::<[u8; 32]>,
+
+    // Set when desktop uploads go to temporary files instead of RAM
+    spill_dir: Option::<PathBuf>,
+
+    // What desktop uploads kept in RAM may add up to
+    memory_budget: MemoryBudget,
+
+    files: AtomicFiles,
+    clients: AtomicClients,
+
+    zip_checksums: AtomicZipChecksums,
+    chunked_uploads: AtomicChunkedUploads,
+    resumable_uploads: AtomicResumableUploads,
+
+    // Set when `DROPPA_FAIR_DOWNLOADS` is on, shared by all the zip tasks.
+    fair_zip_turn: Option::<AtomicZipTurn>,
+
+    // Set when `DROPPA_CONVERT_HEIC` is on.
+    #[cfg(feature = "heic")]
+    heic_conversion: Option::<heic::Conversion>,
+
+    files_progress_pinger: AtomicProgressPinger,
+
+    // `DROPPA_UPLOAD_RATE` uploads per second per client IP, unless it's 0
+    upload_rate_limiter: Option::<RateLimiter>,
+
+    // `--max-rate`, unless it's `--max-rate-per-transfer`, see `throttle`
+    shared_throttle: Option::<Arc::<Throttle>>,
+
+    // `DROPPA_MAX_UPLOADS` uploads run at once, the rest wait for their turn
+    upload_slots: Arc::<Semaphore>,
+    queued_uploads: Arc::<AtomicUsize>,
+
+    draining: AtomicBool,
+    shutdown: Arc::<Notify>,
+    open_streams: Arc::<AtomicUsize>,
+    active_transfers: Arc::<AtomicUsize>,
+
+    // Bumped by transfers and page loads, `--idle-timeout` counts from it
+    last_activity: AtomicLastActivity,
+
+    // Files in `downloads_dir` that are still being written, removed on shutdown if they never finish
+    partial_writes: AtomicPartialWrites,
+
+    // One per file that `/append/{name}` wrote to, so that two appends to it don't interleave
+    append_locks: AtomicAppendLocks,
+
+    // `history.json` in `downloads_dir`, see `/history`
+    history: Arc::<History>,
+
+    // The latest text snippet, `watch` keeps it around and hands it to whoever subscribes to `/clipboard-events`
+    clipboard: watch::Sender::<String>,
+
+    zipping_progress_sender: AtomicSyncProgressSender,
+
+    zipping_progress_streamer: AtomicProgressStreamer,
+    mobile_files_progress_streamer: AtomicProgressStreamer,
+    desktop_files_progress_streamer: AtomicProgressStreamer
+}
+
+impl Server {
+    #[inline(always)]
+    fn lock_streamer(&self, transmission: Transmission) -> impl Future::<Output = TokioMutexGuard::<ProgressStreamer>> {
+        use Transmission::*;
+        match transmission {
+            Mobile  => self.mobile_files_progress_streamer.lock(),
+            Zipping => self.zipping_progress_streamer.lock(),
+            Desktop => self.desktop_files_progress_streamer.lock()
+        }
+    }
+
+    #[inline(always)]
+    async fn streamer_send(&self, json: String, transmission: Transmission) {
+        if let Err(e) = self.lock_streamer(transmission).await.as_ref().expect("SENDER IS NOT INITIALIZED").send(json) {
+            log::error!("could not send JSON: {e}")
+        }
+    }
+
+    // Several tabs may watch the same upload, `watch` fans out to all of them, so don't replace the sender.
+    // The new one starts with the last event that was sent, so a tab that lost its stream doesn't go back to 0.
+    fn subscribe_progress(&self, file_name: String, mobile: bool) -> watch::Receiver::<ProgressEvent> {
+        match self.clients.entry(file_name) {
+            dashmap::Entry::Occupied(client) => {
+                log::info!(
+                    "subscribed to existing: {file_name} in the clients hashmap, {count} watching it now",
+                    file_name = client.key(), count = client.get().sender.receiver_count() + 1
+                );
+                client.get().sender.subscribe()
+            }
+            dashmap::Entry::Vacant(entry) => {
+                let tx = watch::channel(ProgressEvent { state: Some(UploadState::Uploading), ..Default::default() }).0;
+                let rx = tx.subscribe();
+
+                log::info!("inserted: {file_name} into the clients hashmap", file_name = entry.key());
+                entry.insert(Client {
+                    sender: tx,
+                    progress: 0,
+                    size: 0,
+                    received: 0,
+                    mobile,
+                    cancel: None,
+                    pause: None,
+                    last_update: None,
+                    rate: Rate::new(0)
+                });
+
+                rx
+            }
+        }
+    }
+
+    fn cancel_upload(&self, file_name: &str) -> Result::<(), (StatusCode, String)> {
+        let Some(client) = self.clients.get(file_name) else {
+            return Err((StatusCode::NOT_FOUND, format!("nobody is uploading: {file_name}")))
+        };
+
+        let Some(cancel) = client.cancel.as_ref() else {
+            return Err((StatusCode::CONFLICT, format!("no upload of: {file_name} is in progress")))
+        };
+
+        cancel.store(true, Ordering::SeqCst);
+        // A paused upload has to get going again to notice
+        if let Some(pause) = client.pause.as_ref() {
+            pause.send_replace(false);
+        }
+        client.sender.send_replace(ProgressEvent { progress: client.progress, state: Some(UploadState::Cancelled), ..Default::default() });
+
+        log::info!("cancelling upload of: {file_name}..");
+        Ok(())
+    }
+
+    fn pause_upload(&self, file_name: &str, pause: bool) -> Result::<(), (StatusCode, String)> {
+        let Some(client) = self.clients.get(file_name) else {
+            return Err((StatusCode::NOT_FOUND, format!("nobody is uploading: {file_name}")))
+        };
+
+        let Some(paused) = client.pause.as_ref() else {
+            return Err((StatusCode::CONFLICT, format!("no upload of: {file_name} is in progress")))
+        };
+
+        if paused.send_replace(pause) == pause {
+            return Ok(())
+        }
+
+        let state = if pause { UploadState::Paused } else { UploadState::Uploading };
+        client.sender.send_replace(ProgressEvent { progress: client.progress, state: Some(state), ..Default::default() });
+
+        log::info!("{action} upload of: {file_name}..", action = if pause { "pausing" } else { "resuming" });
+        Ok(())
+    }
+
+    // How many of the `held` files have to go for `incoming` more to fit under `--max-files`, if that's allowed.
+    fn make_room(&self, held: usize, incoming: usize) -> Result::<usize, HttpResponse> {
+        let Some(max) = self.config.max_files else {
+            return Ok(0)
+        };
+
+        let excess = (held + incoming).saturating_sub(max);
+        if excess > 0 && (!self.config.evict_oldest || incoming > max) {
+            return Err(HttpResponse::InsufficientStorage().body(format!("that's more than the maximum of {max} files, delete some first")))
+        }
+
+        Ok(excess)
+    }
+
+    // What a transfer is held to by `--max-rate`, its own bucket with `--max-rate-per-transfer`
+    fn throttle(&self) -> Option::<Arc::<Throttle>> {
+        let rate = self.config.max_rate?;
+        if self.config.max_rate_per_transfer {
+            return Some(Arc::new(Throttle::new(rate)))
+        }
+        self.shared_throttle.clone()
+    }
+
+    // Off the async threads, `History::record` rewrites the whole file
+    fn record_transfers(&self, direction: Direction, files: impl Iterator::<Item = (String, usize)>, peer: Option::<IpAddr>) {
+        let entries = files.map(|(name, size)| history::Entry::new(direction, name, size, peer)).collect();
+        let history = Arc::clone(&self.history);
+        actix_rt::task::spawn_blocking(move || history.record(entries));
+    }
+
+    #[inline(always)]
+    fn touch(&self) {
+        *self.lock_last_activity() = Instant::now()
+    }
+
+    #[inline]
+    fn start_transfer(&self) -> Result::<TransferGuard, HttpResponse> {
+        if self.draining.load(Ordering::SeqCst) {
+            return Err(HttpResponse::ServiceUnavailable().body("droppa is draining, not accepting new transfers"))
+        }
+
+        self.touch();
+        Ok(TransferGuard::new(&self.active_transfers))
+    }
+
+    fn limit_upload_rate(&self, rq: &HttpRequest) -> Result::<(), HttpResponse> {
+        let (Some(limiter), Some(peer)) = (self.upload_rate_limiter.as_ref(), rq.peer_addr()) else {
+            return Ok(())
+        };
+
+        limiter.check(peer.ip()).map_err(|wait| {
+            log::info!("too many uploads from: {ip}, rejecting..", ip = peer.ip());
+            HttpResponse::TooManyRequests()
+                .append_header(("Retry-After", wait.as_secs_f64().ceil().max(1.0).to_string()))
+                .body("too many uploads, slow down")
+        })
+    }
+
+    // A chunk that couldn't be written leaves a hole, nothing that comes after it can fill, so it all goes.
+    fn abandon_chunked_upload(&self, upload_id: &str) {
+        let Some((_, upload)) = self.chunked_uploads.remove(upload_id) else {
+            return
+        };
+
+        self.partial_writes.remove(&upload.part);
+        if let Err(e) = fs::remove_file(&upload.part) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                log::error!("could not remove: {part}: {e}", part = upload.part.display())
+            }
+        }
+    }
+
+    // Waits until fewer than `max_uploads` uploads are running.
+    async fn upload_slot(&self) -> Result::<OwnedSemaphorePermit, HttpResponse> {
+        let _queued = TransferGuard::new(&self.queued_uploads);
+        Arc::clone(&self.upload_slots).acquire_owned().await.map_err(|_| {
+            HttpResponse::ServiceUnavailable().body("droppa is shutting down, not accepting new uploads")
+        })
+    }
+
+    // Blocking, since it may have to decode and re-encode an image.
+    #[inline]
+    fn convert_heic(&self, file: File) -> Vec::<File> {
+        #[cfg(feature = "heic")]
+        if let Some(conversion) = self.heic_conversion.as_ref() {
+            return heic::convert(file, conversion)
+        }
+
+        vec![file]
+    }
+
+    // Blocking, writes `files` into `downloads_dir`, or into their label's sub-directory.
+    // Existing files are never overwritten, `files` are renamed to the names they were saved under instead.
+    fn save_to_downloads(&self, files: &mut [File]) -> Result::<(), (StatusCode, String)> {
+        // The directory could've been deleted or its drive unmounted while we're running
+        if !self.config.downloads_dir.exists() {
+            log::warn!("downloads directory: {dir} is gone, recreating it..", dir = self.config.downloads_dir.display());
+            if let Err(e) = fs::create_dir_all(&self.config.downloads_dir) {
+                return Err((write_error_status(&e), format!("could not recreate downloads directory: {e}")))
+            }
+        }
+
+        check_free_space(&self.config.downloads_dir, files.iter().map(|file| file.size as u64).sum::<u64>())?;
+
+        for File { data, name, size, label, dir, .. } in files.iter_mut() {
+            let dir = target_dir(&self.config.downloads_dir, label.as_deref(), dir.as_deref());
+            if let Err(e) = fs::create_dir_all(&dir) {
+                return Err((StatusCode::INTERNAL_SERVER_ERROR, format!("could not create directory: {dir}: {e}", dir = dir.display())))
+            }
+
+            // `create_new` so that two uploads of the same name at once can't both pick the same free one
+            let (file, file_path) = {
+                let mut n = 0;
+                loop {
+                    let file_path = dir.join(numbered_name(name, n));
+                    match fs::OpenOptions::new().write(true).create_new(true).open(&file_path) {
+                        Ok(file) => break (file, file_path),
+                        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => n += 1,
+                        Err(e) if is_read_only(&e) => {
+                            log::error!("downloads directory: {dir} is not writable: {e}", dir = dir.display());
+                            return Err((StatusCode::INTERNAL_SERVER_ERROR, format!("could not create file: {name}: the downloads directory is not writable")))
+                        }
+                        Err(e) => return Err((write_error_status(&e), format!("could not create file: {name}: {e}")))
+                    }
+                }
+            };
+
+            self.partial_writes.insert(file_path.clone());
+            *name = file_path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+
+            log::info!("copying bytes to: {file_path}..", file_path = file_path.display());
+
+            let mut wbuf = BufWriter::with_capacity(*size, file);
+            if let Err(e) = data.copy_to(&mut wbuf).and_then(|_| wbuf.flush()) {
+                drop(wbuf);
+                _ = fs::remove_file(&file_path);
+                self.partial_writes.remove(&file_path);
+                return Err((write_error_status(&e), format!("could not copy bytes: {name}: {e}")))
+            }
+
+            self.partial_writes.remove(&file_path);
+
+            log::info!("saved: {name}");
+        }
+
+        Ok(())
+    }
+
+    // Blocking, unpacks `archive` into a directory named after it, next to it, and returns how many files came out.
+    // On failure whatever was unpacked so far is removed again, the archive itself is always left alone then.
+    fn extract_archive(&self, archive: &std::path::Path) -> Result::<usize, String> {
+        let file = fs::File::open(archive).map_err(|e| format!("could not open: {e}"))?;
+        let mut zip = zip::ZipArchive::new(file).map_err(|e| format!("not a valid zip: {e}"))?;
+
+        let dest = free_path(&archive.with_extension(""));
+        fs::create_dir_all(&dest).map_err(|e| format!("could not create directory: {dest}: {e}", dest = dest.display()))?;
+
+        let extracted = unzip_into(&mut zip, &dest, self.config.size_limit);
+        match extracted {
+            Ok(count) => log::info!("extracted {count} files into: {dest}", dest = dest.display()),
+            Err(..) => _ = fs::remove_dir_all(&dest)
+        }
+
+        extracted
+    }
+
+    // Either in the `X-Droppa-Token` header, or in the `token` query parameter for `EventSource`s and links
+    fn authorize(&self, rq: &HttpRequest) -> bool {
+        let Some(password) = self.config.password.as_ref() else {
+            return true
+        };
+
+        let header = rq.headers().get(PASSWORD_HEADER).map(|h| h.as_bytes().to_vec());
+        let query = Query::<TokenQuery>::from_query(rq.query_string()).ok().and_then(|q| q.into_inner().token).map(String::into_bytes);
+        [header, query].into_iter().flatten().any(|token| constant_time_eq(&token, password.as_bytes()))
+    }
+
+    lock_fn! { files, qr_bytes, qr_target, last_activity }
+}
+
+#[derive(Deserialize)]
+struct TokenQuery {
+    token: Option::<String>
+}
+
+// Handlers that take it answer 401 when `--password` is set and the request doesn't carry it.
+struct Authorized;
+
+impl actix_web::FromRequest for Authorized {
+    type Error = actix_web::Error;
+    type Future = future::Ready::<Result::<Self, Self::Error>>;
+
+    fn from_request(rq: &HttpRequest, _: &mut actix_web::dev::Payload) -> Self::Future {
+        let authorized = rq.app_data::<Data::<Server>>().is_none_or(|state| state.authorize(rq));
+        future::ready(if authorized {
+            Ok(Authorized)
+        } else {
+            Err(actix_web::error::ErrorUnauthorized("missing or invalid password"))
+        })
+    }
+}
+
+#[inline]
+fn user_agent_is_mobile(user_agent: &str) -> bool {
+    [
+        "Mobile",        // General mobile indicator
+        "Android",       // Android devices
+        "iPhone",        // iPhones
+        "iPod",          // iPods
+        "BlackBerry",    // BlackBerry devices
+        "Windows Phone", // Windows Phones
+        "Opera Mini",    // Opera Mini browser
+        "IEMobile",      // Internet Explorer Mobile
+    ].iter().any(|keyword| user_agent.contains(keyword))
+}
+
+// We only find out that an SSE client went away when writing to it, so poke idle streams every now and then
+fn sse_keepalive<T>() -> impl futures_util::Stream::<Item = Option::<T>> {
+    futures_util::stream::unfold((), |()| async {
+        tokio_sleep(TokioDuration::from_secs(SSE_KEEPALIVE_SECS)).await;
+        Some((None, ()))
+    })
+}
+
+/// Progress of the upload of `file_name`, as SSE. Unnamed events carry a `ProgressEvent` as JSON,
+/// the last one is a `done` event carrying the final one, with `"state"` being `done`, `failed` or `cancelled`,
+/// after which the stream ends.
+#[get("/progress/{file_name}")]
+async fn track_progress(_: Authorized, rq: HttpRequest, path: Path::<String>, state: Data::<Server>) -> impl Responder {
+    let Some(user_agent) = rq.headers().get("User-Agent").and_then(|header| header.to_str().ok()) else {
+        return HttpResponse::BadRequest().body("Request to `/` that does not contain user agent")
+    };
+
+    if state.draining.load(Ordering::SeqCst) {
+        return HttpResponse::ServiceUnavailable().body("droppa is draining, not accepting new transfers")
+    }
+
+    let file_name = path.into_inner();
+    log::info!("client connected to <http://localhost:{port}/progress/{file_name}>", port = state.config.port);
+
+    // Lives as long as the stream does
+    let stream_guard = TransferGuard::new(&state.open_streams);
+
+    let rx = state.subscribe_progress(file_name, user_agent_is_mobile(user_agent));
+
+    // The stream ends once the client is removed, after its last event went out
+    let mut closed = rx.clone();
+    let closed = async move { while closed.changed().await.is_ok() {} };
+
+    let last = rx.clone();
+    let events = WatchStream::new(rx)
+        .map(|event| format!("data: {json}\n\n", json = serde_json::to_string(&event).unwrap()))
+        .chain(futures_util::stream::once(async move {
+            format!("event: done\ndata: {json}\n\n", json = serde_json::to_string(&*last.borrow()).unwrap())
+        }));
+
+    let keepalive = sse_keepalive::<()>().take_until(closed).map(|_| ": keepalive\n\n".to_owned());
+
+    HttpResponse::Ok()
+        .append_header(("Content-Type", "text/event-stream"))
+        .append_header(("Cache-Control", "no-cache"))
+        .append_header(("Connection", "keep-alive"))
+        .streaming(futures_util::stream::select(events, keepalive).map(move |event| {
+            let _ = &stream_guard;
+            Ok::<_, actix_web::Error>(event.into())
+        }))
+}
+
+/// Stops the upload of `file_name` that's in progress and throws away what was received of it so far.
+/// Whoever watches `/progress/{file_name}` gets a final event with `"state": "cancelled"`.
+#[post("/cancel/{file_name}")]
+async fn cancel_upload(_: Authorized, path: Path::<String>, state: Data::<Server>) -> impl Responder {
+    match state.cancel_upload(&path.into_inner()) {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err((status, e)) => HttpResponse::build(status).body(e)
+    }
+}
+
+// Weak, because `Compress` may hand out the same bytes gzipped.
+#[inline]
+fn etag_of(bytes: &[u8]) -> EntityTag {
+    let mut hasher = DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    EntityTag::new_weak(format!("{:016x}", hasher.finish()))
+}
+
+#[inline]
+fn is_fresh(rq: &HttpRequest, etag: &EntityTag) -> bool {
+    match IfNoneMatch::parse(rq) {
+        Ok(IfNoneMatch::Any) => true,
+        Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(etag)),
+        Err(_) => false
+    }
+}
+
+// `no-cache` still lets the browser keep its copy, it just has to ask whether the copy is still good.
+fn cached_response(rq: &HttpRequest, content_type: &str, bytes: web::Bytes) -> HttpResponse {
+    let etag = etag_of(&bytes);
+    if is_fresh(rq, &etag) {
+        return HttpResponse::NotModified()
+            .insert_header(("ETag", etag.to_string()))
+            .insert_header(("Cache-Control", "no-cache"))
+            .finish()
+    }
+
+    HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header(("ETag", etag.to_string()))
+        .insert_header(("Cache-Control", "no-cache"))
+        .body(bytes)
+}
+
+/// Stops reading the upload of `file_name` without dropping the connection, until `/resume/{file_name}`.
+/// Whoever watches `/progress/{file_name}` gets an event with `"state": "paused"`.
+#[post("/pause/{file_name}")]
+async fn pause_upload(_: Authorized, path: Path::<String>, state: Data::<Server>) -> impl Responder {
+    match state.pause_upload(&path.into_inner(), true) {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err((status, e)) => HttpResponse::build(status).body(e)
+    }
+}
+
+/// Picks a paused upload of `file_name` back up.
+#[post("/resume/{file_name}")]
+async fn resume_upload(_: Authorized, path: Path::<String>, state: Data::<Server>) -> impl Responder {
+    match state.pause_upload(&path.into_inner(), false) {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err((status, e)) => HttpResponse::build(status).body(e)
+    }
+}
+
+// `--assets-dir`'s `name` if it has one, read on every request so that edits show up on reload, or the built-in one.
+async fn asset(state: &Server, name: &str, builtin: &'static [u8]) -> web::Bytes {
+    let Some(dir) = state.config.assets_dir.as_ref() else {
+        return web::Bytes::from_static(builtin)
+    };
+
+    let path = dir.join(name);
+    match tokio::fs::read(&path).await {
+        Ok(bytes) => web::Bytes::from(bytes),
+        Err(e) => {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                log::warn!("could not read: {path}: {e}, serving the built-in one", path = path.display())
+            }
+            web::Bytes::from_static(builtin)
+        }
+    }
+}
+
+#[get("/")]
+async fn index(rq: HttpRequest, state: Data::<Server>) -> impl Responder {
+    let Some(user_agent) = rq.headers().get("User-Agent").and_then(|header| header.to_str().ok()) else {
+        return HttpResponse::BadRequest().body("Request to `/` that does not contain user agent")
+    };
+
+    state.touch();
+
+    let html = if user_agent_is_mobile(user_agent) {
+        asset(&state, "index-mobile.html", HOME_MOBILE_HTML).await
+    } else {
+        asset(&state, "index-desktop.html", HOME_DESKTOP_HTML).await
+    };
+    cached_response(&rq, "text/html", html)
+}
+
+// Browsers ask for `/favicon.ico` on their own, the pages link `/droppa.ico`, both are there without `./front`
+#[routes]
+#[get("/favicon.ico")]
+#[get("/droppa.ico")]
+async fn favicon(rq: HttpRequest, state: Data::<Server>) -> impl Responder {
+    cached_response(&rq, "image/x-icon", asset(&state, "droppa.ico", FAVICON).await)
+}
+
+// Whatever no route or file is there for
+async fn not_found(state: Data::<Server>) -> HttpResponse {
+    HttpResponse::NotFound().content_type("text/html").body(asset(&state, "404.html", NOT_FOUND_HTML).await)
+}
+
+#[get("/qr.png")]
+async fn qr_code(_: Authorized, rq: HttpRequest, state: Data::<Server>) -> impl Responder {
+    let qr_bytes = web::Bytes::clone(&state.lock_qr_bytes());
+    let mut rsp = cached_response(&rq, "image/png", qr_bytes);
+    rsp.headers_mut().insert(actix_web::http::header::CONTENT_ENCODING, ContentEncoding::Identity.to_header_value());
+    rsp
+}
+
+/// Joins the `--wifi-ssid` network, scanned before `/qr.png` so that the phone can reach us. 404 without `--wifi-ssid`.
+#[get("/wifi-qr.png")]
+async fn wifi_qr_code(_: Authorized, rq: HttpRequest, state: Data::<Server>) -> impl Responder {
+    let Some(qr_bytes) = state.wifi_qr_bytes.clone() else {
+        return HttpResponse::NotFound().body("droppa was started without `--wifi-ssid`")
+    };
+
+    let mut rsp = cached_response(&rq, "image/png", qr_bytes);
+    rsp.headers_mut().insert(actix_web::http::header::CONTENT_ENCODING, ContentEncoding::Identity.to_header_value());
+    rsp
+}
+
+#[derive(Clone, Serialize)]
+pub struct ServerInfo {
+    url: String,
+    ip: IpAddr,
+    port: u16
+}
+
+/// What the QR code points at, as `{ url, ip, port }`, so that pages can spell it out next to it.
+#[get("/info")]
+async fn server_info(_: Authorized, state: Data::<Server>) -> impl Responder {
+    HttpResponse::Ok().json(&*state.lock_qr_target())
+}
+
+#[derive(Serialize)]
+struct RegeneratedQr {
+    url: String
+}
+
+/// Looks the local IP address up again and swaps `/qr.png` for a code of the new URL, for when the machine changed networks.
+#[post("/regenerate-qr")]
+async fn regenerate_qr(_: Authorized, state: Data::<Server>) -> impl Responder {
+    if let Some(ip) = state.config.bind_ip {
+        return HttpResponse::Conflict().body(format!("droppa is bound to: {ip} with `--bind-ip`, there's nothing to re-detect"))
+    }
+
+    let Some(ip) = get_default_local_ip_addr(state.config.prefer_ipv6) else {
+        return HttpResponse::ServiceUnavailable().body("could not find local IP address")
+    };
+
+    let url = server_url(ip, state.config.port, state.tls_fingerprint.as_ref(), state.config.password.as_deref());
+    let qr_bytes = match QrCode::encode_text(&url, state.qr_ecc).ok().and_then(|qr| gen_qr_png_bytes(&qr).ok()) {
+        Some(qr_bytes) => qr_bytes,
+        None => return HttpResponse::InternalServerError().body("could not generate QR code")
+    };
+
+    *state.lock_qr_bytes() = qr_bytes.into();
+    *state.lock_qr_target() = ServerInfo { url: url.clone(), ip, port: state.config.port };
+
+    log::info!("regenerated QR code, now serving at: <{url}>");
+    HttpResponse::Ok().json(RegeneratedQr { url })
+}
+
+#[post("/upload-desktop")]
+async fn upload_desktop(_: Authorized, rq: HttpRequest, mut multipart: Multipart, state: Data::<Server>) -> impl Responder {
+    let started = Instant::now();
+    let _transfer = match state.start_transfer() {
+        Ok(transfer) => transfer,
+        Err(rsp) => return rsp
+    };
+
+    if let Err(rsp) = state.limit_upload_rate(&rq) {
+        return rsp
+    }
+
+    let _slot = match state.upload_slot().await {
+        Ok(slot) => slot,
+        Err(rsp) => return rsp
+    };
+
+    // At least one file is coming, no need to read any of them if there's no room for it
+    let held = state.lock_files().len();
+    if let Err(rsp) = state.make_room(held, 1) {
+        return rsp
+    }
+
+    log::info!("upload-desktop requested, parsing multipart..");
+
+    let dest = state.spill_dir.as_deref().map_or(Destination::Memory(Some(&state.memory_budget)), Destination::Spill);
+
+    let files = match File::from_multipart(&mut multipart, Arc::clone(&state.clients), Arc::clone(&state.files_progress_pinger), dest, content_length(&rq), &state.config, state.throttle()).await {
+        Ok(files) => files,
+        Err(e) => return e.response()
+    };
+
+    for file in files.iter() {
+        log::info!("uploaded: {name}", name = file.name)
+    }
+
+    store_desktop_uploads("upload-desktop", files, &rq, state, started).await
+}
+
+// What the desktop uploads go through once they're received: HEIC conversion, `--persist-desktop`, `--max-files`,
+// and names that are free among the files that are already there.
+async fn store_desktop_uploads(endpoint: &str, files: Vec::<File>, rq: &HttpRequest, state: Data::<Server>, started: Instant) -> HttpResponse {
+    // Checked again once they're stored, this is so that nothing is converted or saved for nothing
+    let held = state.lock_files().len();
+    if let Err(rsp) = state.make_room(held, files.len()) {
+        return rsp
+    }
+
+    let mut files = {
+        let state = Data::clone(&state);
+        match actix_rt::task::spawn_blocking(move || files.into_iter().flat_map(|file| state.convert_heic(file)).collect::<Vec::<_>>()).await {
+            Ok(files) => files,
+            Err(e) => return HttpResponse::InternalServerError().body(format!("error converting file: {e}"))
+        }
+    };
+
+    // The in-memory copy stays around for zipping, the one on disk survives a crash
+    if state.config.persist_desktop {
+        let state = Data::clone(&state);
+        match actix_rt::task::spawn_blocking(move || state.save_to_downloads(&mut files).map(|_| files)).await {
+            Ok(Ok(saved)) => files = saved,
+            Ok(Err((status, e))) => {
+                log::error!("{e}");
+                return HttpResponse::build(status).body(e)
+            }
+            Err(e) => return HttpResponse::InternalServerError().body(format!("error copying bytes: {e}"))
+        }
+    }
+
+    let uploaded = {
+        let mut stored = state.lock_files();
+        let evict = match state.make_room(stored.len(), files.len()) {
+            Ok(evict) => evict,
+            Err(rsp) => return rsp
+        };
+
+        // They're kept in the order they came in
+        for evicted in stored.drain(..evict) {
+            log::info!("evicted: {name} to make room for new uploads", name = evicted.name)
+        }
+
+        for file in files.iter_mut() {
+            let free = (0..).map(|n| numbered_name(&file.name, n)).find(|name| stored.iter().all(|f| &f.name != name)).unwrap();
+            file.name = free
+        }
+
+        let uploaded = files.iter().map(FileEntry::from).collect::<Vec::<_>>();
+        stored.extend(files);
+        uploaded
+    };
+
+    log_transfers(endpoint, &uploaded, started, rq.peer_addr().map(|addr| addr.ip()));
+    state.record_transfers(Direction::Upload, uploaded.iter().map(|file| (file.name.clone(), file.size)), rq.peer_addr().map(|addr| addr.ip()));
+    HttpResponse::Ok().json(Uploaded { files: uploaded })
+}
+
+/// Takes an image pasted on the desktop page, e.g. a screenshot, as the raw request body. Pasted images have no name,
+/// so it gets one after when it came in, like `paste-20240101-120000.png`, in UTC, and is stored like `/upload-desktop` ones.
+/// Anything that isn't a PNG, JPEG, GIF, WebP or HEIC image is turned away with 415.
+#[post("/upload-paste")]
+async fn upload_paste(_: Authorized, rq: HttpRequest, mut body: web::Payload, state: Data::<Server>) -> impl Responder {
+    let started = Instant::now();
+    let _transfer = match state.start_transfer() {
+        Ok(transfer) => transfer,
+        Err(rsp) => return rsp
+    };
+
+    if let Err(rsp) = state.limit_upload_rate(&rq) {
+        return rsp
+    }
+
+    let _slot = match state.upload_slot().await {
+        Ok(slot) => slot,
+        Err(rsp) => return rsp
+    };
+
+    let held = state.lock_files().len();
+    if let Err(rsp) = state.make_room(held, 1) {
+        return rsp
+    }
+
+    let size = content_length(&rq);
+    if let Err(e) = size.map_or(Ok(()), |size| check_size_limit(size, state.config.size_limit)) {
+        drain_payload(&mut body).await;
+        return e.response()
+    }
+
+    if let (Some(dir), Some(size)) = (state.spill_dir.as_deref(), size) {
+        if let Err((status, e)) = check_free_space(dir, size as u64) {
+            return HttpResponse::build(status).body(e)
+        }
+    }
+
+    let mut claim = None;
+    let mut sink = match state.spill_dir.as_deref() {
+        Some(dir) => match Sink::spill(dir).await {
+            Ok(sink) => sink,
+            Err(e) => {
+                log::error!("could not create temporary file in: {dir}: {e}", dir = dir.display());
+                return write_error(&e, "could not create temporary file").response()
+            }
+        },
+        None => {
+            let Some(size) = size else {
+                return HttpResponse::LengthRequired().body("pasted images kept in memory need a Content-Length")
+            };
+
+            let Some(claimed) = state.memory_budget.claim(size) else {
+                return not_enough_memory("the pasted image").response()
+            };
+            claim = Some(Arc::new(claimed));
+            Sink::Memory(Vec::with_capacity(size))
+        }
+    };
+
+    let throttle = state.throttle();
+    let mut hasher = Sha256::new();
+    while let Some(chunk) = body.next().await {
+        let Ok(chunk) = chunk else {
+            return HttpResponse::BadRequest().body("error reading pasted image")
+        };
+
+        if let Err(e) = check_size_limit(sink.len() + chunk.len(), state.config.size_limit) {
+            drain_payload(&mut body).await;
+            return e.response()
+        }
+
+        if let Err(e) = sink.write(&chunk).await {
+            log::error!("could not write pasted image: {e}");
+            return write_error(&e, "could not write file").response()
+        }
+        hasher.update(&chunk);
+
+        if let Some(wait) = throttle.as_ref().map(|throttle| throttle.take(chunk.len())).filter(|wait| !wait.is_zero()) {
+            tokio_sleep(wait).await
+        }
+    }
+
+    let size = sink.len();
+    let data = match sink.finish().await {
+        Ok(data) => data,
+        Err(e) => {
+            log::error!("could not finish writing pasted image: {e}");
+            return write_error(&e, "could not write file").response()
+        }
+    };
+
+    let Some((mime, ext)) = head_of(&data).await.ok().as_deref().and_then(sniff).filter(|(mime, _)| mime.starts_with("image/")) else {
+        return HttpResponse::UnsupportedMediaType().body("that's not an image, expected a PNG, JPEG, GIF, WebP or HEIC one")
+    };
+
+    let modified = SystemTime::now();
+    let name = paste_file_name(modified, ext);
+    log::info!("uploaded: {name} from the clipboard");
+
+    let file = File { size, name, data, label: None, sha256: hex(&hasher.finalize()), claim, modified, dir: None, mime: Some(mime) };
+    store_desktop_uploads("upload-paste", vec![file], &rq, state, started).await
+}
+
+#[derive(Deserialize)]
+struct ResumeQuery {
+    offset: Option::<u64>
+}
+
+/// Receives a file from the phone and saves it to the downloads directory.
+///
+/// With `?offset=N` the upload is resumable: it's written to `<name>.part` as it arrives, starting at byte `N`,
+/// and only renamed to `<name>` once complete. If the connection drops, ask `/upload-status/{name}` how much
+/// made it and send the rest of the file with that offset. The `size` field is always the total size.
+#[post("/upload-mobile")]
+async fn upload_mobile(_: Authorized, rq: HttpRequest, mut multipart: Multipart, query: Query::<ResumeQuery>, state: Data::<Server>) -> impl Responder {
+    let started = Instant::now();
+    let _transfer = match state.start_transfer() {
+        Ok(transfer) => transfer,
+        Err(rsp) => return rsp
+    };
+
+    if let Err(rsp) = state.limit_upload_rate(&rq) {
+        return rsp
+    }
+
+    let _slot = match state.upload_slot().await {
+        Ok(slot) => slot,
+        Err(rsp) => return rsp
+    };
+
+    log::info!("upload-mobile requested, parsing multipart..");
+
+    let dest = match query.offset {
+        Some(offset) => Destination::Resume { dir: &state.config.downloads_dir, offset, totals: &state.resumable_uploads },
+        // Only buffered until it's saved
+        None => Destination::Memory(None)
+    };
+
+    // Checked again once it's saved, by then the whole body was already received for nothing
+    if let (Destination::Memory(..), Some(size)) = (dest, content_length(&rq)) {
+        if let Err((status, e)) = check_free_space(&state.config.downloads_dir, size as u64) {
+            return HttpResponse::build(status).body(e)
+        }
+    }
+
+    let files = match File::from_multipart(&mut multipart, Arc::clone(&state.clients), Arc::clone(&state.files_progress_pinger), dest, content_length(&rq), &state.config, state.throttle()).await {
+        Ok(files) => files,
+        Err(e) => return e.response()
+    };
+
+    // A resumed upload is always the only file of its request, and already where it belongs
+    if let [file @ File { data: FileData::Saved(path), .. }] = files.as_slice() {
+        log::info!("uploaded: {path}", path = path.display());
+        let files = vec![FileEntry::from(file)];
+        log_transfers("upload-mobile", &files, started, rq.peer_addr().map(|addr| addr.ip()));
+        state.record_transfers(Direction::Upload, files.iter().map(|file| (file.name.clone(), file.size)), rq.peer_addr().map(|addr| addr.ip()));
+        return HttpResponse::Ok().json(Uploaded { files })
+    }
+
+    // Saved under their final name, which is also what the response carries
+    let mut files = files;
+    if state.config.debug_suffix {
+        for file in files.iter_mut() { file.name += ".test" }
+    }
+
+    let peer = rq.peer_addr().map(|addr| addr.ip());
+    let rsp = actix_rt::task::spawn_blocking(move || {
+        let mut files = files.into_iter().flat_map(|file| state.convert_heic(file)).collect::<Vec::<_>>();
+        state.save_to_downloads(&mut files)?;
+
+        let mut entries = files.iter().map(FileEntry::from).collect::<Vec::<_>>();
+        if state.config.auto_extract {
+            for (file, entry) in files.iter().zip(entries.iter_mut()) {
+                if !file.name.to_ascii_lowercase().ends_with(".zip") {
+                    continue
+                }
+
+                let path = target_dir(&state.config.downloads_dir, file.label.as_deref(), file.dir.as_deref()).join(&file.name);
+                match state.extract_archive(&path) {
+                    Ok(count) => {
+                        entry.extracted = Some(count);
+                        if state.config.delete_archives {
+                            if let Err(e) = fs::remove_file(&path) {
+                                log::warn!("could not remove extracted archive: {path}: {e}", path = path.display())
+                            }
+                        }
+                    }
+                    // It was still received fine, it's only left packed
+                    Err(e) => log::warn!("could not extract: {name}: {e}", name = file.name)
+                }
+            }
+        }
+
+        // Already off the async threads
+        state.history.record(entries.iter().map(|entry| history::Entry::new(Direction::Upload, entry.name.clone(), entry.size, peer)).collect());
+        Ok(entries)
+    }).await;
+
+    match rsp {
+        Ok(Ok(files)) => {
+            log_transfers("upload-mobile", &files, started, peer);
+            HttpResponse::Ok().json(Uploaded { files })
+        }
+        Ok(Err((status, e))) => {
+            log::error!("{e}");
+            HttpResponse::build(status).body(e)
+        }
+        Err(e) => HttpResponse::InternalServerError().body(format!("error copying bytes: {e}"))
+    }
+}
+
+#[derive(Deserialize)]
+struct UploadStatusQuery {
+    label: Option::<String>,
+    // The `path` the upload was sent with
+    path: Option::<String>
+}
+
+#[derive(Deserialize)]
+struct UploadCheck {
+    name: String,
+    size: usize
+}
+
+#[derive(Serialize)]
+struct UploadCheckResult {
+    ok: bool
+}
+
+/// Whether an upload of `{ "name": .., "size": .. }` would be taken, before a single byte of it is sent.
+/// Runs the checks the upload itself would, the page asking decides which: phones upload to disk, desktops to memory,
+/// unless `--spill-to-disk` or `--persist-desktop`. Responds with `{ "ok": true }`, or with the status and reason the upload would fail with.
+#[post("/upload-check")]
+async fn upload_check(_: Authorized, rq: HttpRequest, check: web::Json::<UploadCheck>, state: Data::<Server>) -> impl Responder {
+    if state.draining.load(Ordering::SeqCst) {
+        return HttpResponse::ServiceUnavailable().body("droppa is draining, not accepting new transfers")
+    }
+
+    let UploadCheck { name, size } = check.into_inner();
+    let name = match sanitize_file_name(&name) {
+        Ok(name) => name,
+        Err(e) => return HttpResponse::BadRequest().body(e)
+    };
+
+    if let Err(e) = check_size_limit(size, state.config.size_limit) {
+        return e.response()
+    }
+
+    let mobile = rq.headers().get("User-Agent").and_then(|header| header.to_str().ok()).is_some_and(user_agent_is_mobile);
+    if !mobile {
+        let held = state.lock_files().len();
+        if let Err(rsp) = state.make_room(held, 1) {
+            return rsp
+        }
+
+        if state.spill_dir.is_none() && !state.memory_budget.fits(size) {
+            return not_enough_memory(&name).response()
+        }
+    }
+
+    if mobile || state.spill_dir.is_some() || state.config.persist_desktop {
+        if let Err((status, e)) = check_free_space(&state.config.downloads_dir, size as u64) {
+            return HttpResponse::build(status).body(e)
+        }
+    }
+
+    HttpResponse::Ok().json(UploadCheckResult { ok: true })
+}
+
+#[derive(Serialize)]
+struct UploadStatus {
+    received: u64,
+    // Unknown if the partial file was left behind by a previous run
+    total: Option::<usize>,
+    complete: bool
+}
+
+/// How much of a resumable `/upload-mobile?offset=N` upload is already on disk, pass `received` as the next offset.
+/// Takes the `label` and `path` the upload was sent with, if any, as `?label=..&path=..`.
+#[get("/upload-status/{name}")]
+async fn upload_status(_: Authorized, path: Path::<String>, query: Query::<UploadStatusQuery>, state: Data::<Server>) -> impl Responder {
+    let requested = path.into_inner();
+    let name = match sanitize_file_name(&requested) {
+        Ok(name) => name,
+        Err(e) => return HttpResponse::BadRequest().body(e)
+    };
+    let subdir = match query.path.as_deref().map(sanitize_dir) {
+        Some(Ok(subdir)) => subdir,
+        Some(Err(e)) => return HttpResponse::BadRequest().body(e),
+        None => None
+    };
+
+    let label = query.label.as_deref().and_then(sanitize_label);
+    let dir = target_dir(&state.config.downloads_dir, label.as_deref(), subdir.as_deref());
+
+    let part = dir.join(format!("{name}.part"));
+    let status = if let Ok(meta) = tokio::fs::metadata(&part).await {
+        UploadStatus {
+            received: meta.len(),
+            total: state.resumable_uploads.get(&part).map(|total| *total),
+            complete: false
+        }
+    } else if let Ok(meta) = tokio::fs::metadata(dir.join(&name)).await {
+        UploadStatus { received: meta.len(), total: Some(meta.len() as usize), complete: true }
+    } else {
+        UploadStatus { received: 0, total: None, complete: false }
+    };
+
+    HttpResponse::Ok().json(status)
+}
+
+pub struct ChunkedUpload {
+    name: String,
+    // Of this upload alone, two uploads of the same name don't share one
+    part: PathBuf,
+    total: u64,
+    // Sorted, non-overlapping, half-open ranges of bytes that already landed on disk
+    received: Vec::<(u64, u64)>
+}
+
+impl ChunkedUpload {
+    fn insert(&mut self, (start, end): (u64, u64)) {
+        self.received.push((start, end));
+        self.received.sort_unstable();
+
+        let mut merged = Vec::<(u64, u64)>::with_capacity(self.received.len());
+        for (start, end) in self.received.drain(..) {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end))
+            }
+        }
+
+        self.received = merged
+    }
+
+    #[inline]
+    fn received_bytes(&self) -> u64 {
+        self.received.iter().map(|(start, end)| end - start).sum()
+    }
+
+    #[inline]
+    fn is_complete(&self) -> bool {
+        self.received.as_slice() == [(0, self.total)]
+    }
+}
+
+// Parses `Content-Range: bytes <start>-<end>/<total>` into a half-open range and the total size.
+fn parse_content_range(header: &str) -> Option::<((u64, u64), u64)> {
+    let (range, total) = header.strip_prefix("bytes ")?.split_once('/')?;
+    let (start, end) = range.split_once('-')?;
+    let (start, end, total) = (start.parse::<u64>().ok()?, end.parse::<u64>().ok()?, total.parse::<u64>().ok()?);
+    (start <= end && end < total).then_some(((start, end + 1), total))
+}
+
+#[derive(Serialize)]
+struct Appended {
+    // Of the whole file, with what was just appended
+    size: u64
+}
+
+/// Appends the raw body to `<name>` in the downloads directory, creating it if it isn't there, unlike uploads,
+/// which save under a free name. Meant for sending a log over as it grows, see `/tail/{name}`.
+/// Each request's body goes in whole, appends to the same file one after the other. Responds with `{ "size": .. }`.
+/// Bodies over 1 MiB are turned away with 413.
+#[post("/append/{name}")]
+async fn append_file(_: Authorized, rq: HttpRequest, path: Path::<String>, mut body: web::Payload, state: Data::<Server>) -> impl Responder {
+    let _transfer = match state.start_transfer() {
+        Ok(transfer) => transfer,
+        Err(rsp) => return rsp
+    };
+
+    if let Err(rsp) = state.limit_upload_rate(&rq) {
+        return rsp
+    }
+
+    let _slot = match state.upload_slot().await {
+        Ok(slot) => slot,
+        Err(rsp) => return rsp
+    };
+
+    let name = match sanitize_file_name(&path.into_inner()) {
+        Ok(name) => name,
+        Err(e) => return HttpResponse::BadRequest().body(e)
+    };
+
+    let limit = MAX_APPEND_LEN.min(state.config.size_limit);
+    if let Err(e) = content_length(&rq).map_or(Ok(()), |length| check_size_limit(length, limit)) {
+        drain_payload(&mut body).await;
+        return e.response()
+    }
+
+    // Received in full before taking the lock, so that a slow sender doesn't hold up the others
+    let Some(mut claim) = state.memory_budget.claim(0) else {
+        return not_enough_memory(&name).response()
+    };
+
+    let throttle = state.throttle();
+    let mut chunk = web::BytesMut::new();
+    while let Some(bytes) = body.next().await {
+        let Ok(bytes) = bytes else {
+            return HttpResponse::BadRequest().body("error reading appended bytes")
+        };
+
+        if let Err(e) = check_size_limit(chunk.len() + bytes.len(), limit) {
+            drain_payload(&mut body).await;
+            return e.response()
+        }
+
+        if !claim.grow_to(chunk.len() + bytes.len()) {
+            drain_payload(&mut body).await;
+            return not_enough_memory(&name).response()
+        }
+
+        chunk.extend_from_slice(&bytes);
+
+        if let Some(wait) = throttle.as_ref().map(|throttle| throttle.take(bytes.len())).filter(|wait| !wait.is_zero()) {
+            tokio_sleep(wait).await
+        }
+    }
+
+    if let Err((status, e)) = check_free_space(&state.config.downloads_dir, chunk.len() as u64) {
+        return HttpResponse::build(status).body(e)
+    }
+
+    let path = state.config.downloads_dir.join(&name);
+    let lock = Arc::clone(&state.append_locks.entry(path.clone()).or_default());
+    let _locked = lock.lock().await;
+
+    // `append` is atomic per write on its own, `write_all` may take several of them though
+    let appended = async {
+        let mut file = tokio::fs::OpenOptions::new().append(true).create(true).open(&path).await?;
+        file.write_all(&chunk).await?;
+        file.flush().await?;
+        file.metadata().await.map(|meta| meta.len())
+    }.await;
+
+    // Not recorded in the history, a log sent line by line would flood it
+    match appended {
+        Ok(size) => {
+            log::debug!("appended {len} bytes to: {name}", len = chunk.len());
+            HttpResponse::Ok().json(Appended { size })
+        }
+        Err(e) => {
+            log::error!("could not append to: {name}: {e}");
+            HttpResponse::build(write_error_status(&e)).body(format!("could not append to: {name}"))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct TailQuery {
+    lines: Option::<usize>
+}
+
+/// The last `?lines=` lines (10 by default, 10000 at most) of `<name>` in the downloads directory, as plain text,
+/// e.g. of a log that `/append/{name}` keeps adding to.
+#[get("/tail/{name}")]
+async fn tail_file(_: Authorized, path: Path::<String>, query: Query::<TailQuery>, state: Data::<Server>) -> impl Responder {
+    let name = match sanitize_file_name(&path.into_inner()) {
+        Ok(name) => name,
+        Err(e) => return HttpResponse::BadRequest().body(e)
+    };
+
+    let lines = query.lines.unwrap_or(DEFAULT_TAIL_LINES).min(MAX_TAIL_LINES);
+    let path = state.config.downloads_dir.join(&name);
+    let tail = actix_rt::task::spawn_blocking(move || tail_lines(&path, lines)).await;
+    match tail.map_err(std::io::Error::other).and_then(|r| r) {
+        Ok(tail) => HttpResponse::Ok().content_type("text/plain; charset=utf-8").body(tail),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => HttpResponse::NotFound().body(format!("no such file: {name}")),
+        Err(e) => {
+            log::error!("could not read: {name}: {e}");
+            HttpResponse::InternalServerError().body(format!("could not read: {name}"))
+        }
+    }
+}
+
+// Blocking, reads from the end in blocks until it has `lines` lines, so that a long log isn't read whole.
+fn tail_lines(path: &std::path::Path, lines: usize) -> std::io::Result::<Vec::<u8>> {
+    const BLOCK: u64 = 64 * 1024;
+
+    let mut file = fs::File::open(path)?;
+    let mut pos = file.metadata()?.len();
+    let mut tail = Vec::<u8>::new();
+    loop {
+        // The newline that ends the last line doesn't start another one
+        let body = tail.strip_suffix(b"\n").unwrap_or(&tail);
+        let start = match lines {
+            0 => Some(tail.len()),
+            _ => body.iter().enumerate().rev().filter(|(_, b)| **b == b'\n').nth(lines - 1).map(|(at, _)| at + 1)
+        };
+
+        if let Some(start) = start {
+            return Ok(tail.split_off(start))
+        }
+
+        if pos == 0 {
+            return Ok(tail)
+        }
+
+        let from = pos.saturating_sub(BLOCK);
+        let mut block = vec![0; (pos - from) as usize];
+        file.seek(SeekFrom::Start(from))?;
+        file.read_exact(&mut block)?;
+        block.extend_from_slice(&tail);
+        tail = block;
+        pos = from
+    }
+}
+
+#[derive(Deserialize)]
+struct ChunkQuery {
+    name: Option::<String>
+}
+
+#[derive(Serialize)]
+struct ChunkStatus {
+    received: u64,
+    total: u64,
+    complete: bool,
+    // What the file was saved as, once complete
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option::<String>
+}
+
+/// Receives one chunk of a file that's uploaded as several (possibly parallel) requests.
+///
+/// Each request carries `Content-Range: bytes <start>-<end>/<total>` and the raw chunk as its body, and
+/// optionally `?name=` for the final file name (`upload_id` is used otherwise). Chunks are written straight
+/// into a `.part` file of the upload at their offsets as they come in, once all of `[0, total)` is covered
+/// it's renamed to `<name>`. Each chunk counts as an upload for `DROPPA_UPLOAD_RATE` and `DROPPA_MAX_UPLOADS`.
+#[put("/upload-chunk/{upload_id}")]
+async fn upload_chunk(_: Authorized, rq: HttpRequest, path: Path::<String>, query: Query::<ChunkQuery>, mut body: web::Payload, state: Data::<Server>) -> impl Responder {
+    let _transfer = match state.start_transfer() {
+        Ok(transfer) => transfer,
+        Err(rsp) => return rsp
+    };
+
+    if let Err(rsp) = state.limit_upload_rate(&rq) {
+        return rsp
+    }
+
+    let _slot = match state.upload_slot().await {
+        Ok(slot) => slot,
+        Err(rsp) => return rsp
+    };
+
+    let upload_id = path.into_inner();
+
+    let Some(((start, end), total)) = rq.headers().get("Content-Range").and_then(|h| h.to_str().ok()).and_then(parse_content_range) else {
+        return HttpResponse::BadRequest().body("missing or invalid `Content-Range` header, expected `bytes <start>-<end>/<total>`")
+    };
+
+    if let Err(e) = check_size_limit(total as usize, state.config.size_limit) {
+        return e.response()
+    }
+
+    let requested = query.name.as_deref().unwrap_or(&upload_id);
+    let name = match sanitize_file_name(requested) {
+        Ok(name) => name,
+        Err(e) => return HttpResponse::BadRequest().body(e)
+    };
+
+    let (name, part, remaining) = {
+        let upload = state.chunked_uploads.entry(upload_id.clone()).or_insert_with(|| {
+            // `upload_id` is the client's, only its hash goes into the path
+            let mut hasher = DefaultHasher::new();
+            upload_id.hash(&mut hasher);
+            let part = state.config.downloads_dir.join(format!(".droppa-chunks-{id:016x}.part", id = hasher.finish()));
+
+            // Only renamed once complete, until then it's removed on shutdown like any other partial write
+            state.partial_writes.insert(part.clone());
+            ChunkedUpload { name, part, total, received: Vec::new() }
+        });
+
+        if upload.total != total {
+            return HttpResponse::Conflict().body(format!("upload: {upload_id} was started with total size: {}", upload.total))
+        }
+
+        (upload.name.clone(), upload.part.clone(), upload.total - upload.received_bytes())
+    };
+
+    // What's left of the whole upload, not just this chunk, so that it's turned away before most of it is sent
+    if let Err((status, e)) = check_free_space(&state.config.downloads_dir, remaining) {
+        // Kept if some of it already landed, there may be room once something's deleted
+        if remaining == total {
+            state.abandon_chunked_upload(&upload_id)
+        }
+        return HttpResponse::build(status).body(e)
+    }
+
+    let opened = async {
+        let mut file = tokio::fs::OpenOptions::new().write(true).create(true).truncate(false).open(&part).await?;
+        file.seek(SeekFrom::Start(start)).await?;
+        Ok::<_, std::io::Error>(file)
+    }.await;
+
+    let mut file = match opened {
+        Ok(file) => file,
+        Err(e) => {
+            log::error!("could not open the part file of: {name}: {e}");
+            state.abandon_chunked_upload(&upload_id);
+            return HttpResponse::build(write_error_status(&e)).body(format!("could not write chunk: {e}"))
+        }
+    };
+
+    // Written as it comes in, only the ranges that landed whole are counted as received, a chunk cut short is just sent again
+    let throttle = state.throttle();
+    let mut written = 0;
+    while let Some(bytes) = body.next().await {
+        let Ok(bytes) = bytes else {
+            return HttpResponse::BadRequest().body("error reading chunk")
+        };
+
+        if written + bytes.len() as u64 > end - start {
+            return HttpResponse::BadRequest().body("chunk is larger than its `Content-Range`")
+        }
+
+        if let Err(e) = file.write_all(&bytes).await {
+            log::error!("could not write chunk of: {name}: {e}");
+            state.abandon_chunked_upload(&upload_id);
+            return HttpResponse::build(write_error_status(&e)).body(format!("could not write chunk: {e}"))
+        }
+        written += bytes.len() as u64;
+
+        if let Some(wait) = throttle.as_ref().map(|throttle| throttle.take(bytes.len())).filter(|wait| !wait.is_zero()) {
+            tokio_sleep(wait).await
+        }
+    }
+
+    if written != end - start {
+        return HttpResponse::BadRequest().body("chunk is smaller than its `Content-Range`")
+    }
+
+    // `tokio`'s `File` writes in the background, this is where its errors show up
+    if let Err(e) = file.flush().await {
+        log::error!("could not write chunk of: {name}: {e}");
+        state.abandon_chunked_upload(&upload_id);
+        return HttpResponse::build(write_error_status(&e)).body(format!("could not write chunk: {e}"))
+    }
+    drop(file);
+
+    let mut status = {
+        let Some(mut upload) = state.chunked_uploads.get_mut(&upload_id) else {
+            // Someone else's chunk completed the upload while we were writing ours
+            return HttpResponse::Ok().json(ChunkStatus { received: total, total, complete: true, name: None })
+        };
+
+        upload.insert((start, end));
+        ChunkStatus { received: upload.received_bytes(), total, complete: upload.is_complete(), name: None }
+    };
+
+    if status.complete {
+        state.chunked_uploads.remove(&upload_id);
+
+        let done = free_path(&state.config.downloads_dir.join(&name));
+        let renamed = fs::rename(&part, &done);
+        state.partial_writes.remove(&part);
+        if let Err(e) = renamed {
+            log::error!("could not finalize: {name}: {e}");
+            _ = fs::remove_file(&part);
+            return HttpResponse::InternalServerError().body(format!("could not finalize upload: {e}"))
+        }
+
+        log::info!("uploaded: {done} in chunks", done = done.display());
+        let name = done.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        state.record_transfers(Direction::Upload, std::iter::once((name.clone(), total as usize)), rq.peer_addr().map(|addr| addr.ip()));
+        status.name = Some(name)
+    }
+
+    HttpResponse::Ok().json(status)
+}
+
+// At most one update per `PROGRESS_INTERVAL_MS`, and with `--progress-step` only once it moved by that many percent since `last`.
+// The final 100 always goes out, a step that doesn't divide 100 would never land on it otherwise.
+fn progress_due(last_update: Option::<Instant>, last: u8, progress: u8, step: Option::<u8>) -> bool {
+    let Some(last_update) = last_update else {
+        return true
+    };
+
+    let due = last_update.elapsed() >= TokioDuration::from_millis(PROGRESS_INTERVAL_MS) && step.is_none_or(|step| progress >= last.saturating_add(step));
+    due || (progress == 100 && last != 100)
+}
+
+struct ProgressTracker<W: Write> {
+    writer: W,
+    written: usize,
+    total_size: usize,
+    progress_sender: AtomicSyncProgressSender,
+    turn: Option::<AtomicZipTurn>,
+    // `--progress-step`
+    step: Option::<u8>,
+    // `--max-rate`
+    throttle: Option::<Arc::<Throttle>>,
+    // When a progress update was last sent, and with what
+    last_update: Option::<(Instant, usize)>,
+    rate: Rate
+}
+
+impl<W: Write> ProgressTracker::<W> {
+    #[inline(always)]
+    pub fn new(writer: W, total_size: usize, progress_sender: AtomicSyncProgressSender, turn: Option::<AtomicZipTurn>, step: Option::<u8>, throttle: Option::<Arc::<Throttle>>) -> Self {
+        Self { writer, written: 0, total_size, progress_sender, turn, step, throttle, last_update: None, rate: Rate::new(0) }
+    }
+
+    #[inline]
+    pub fn progress(&self) -> usize {
+        if self.written >= self.total_size {
+            100
+        } else {
+            (self.written * 100 / self.total_size).min(100)
+        }
+    }
+}
+
+impl<W: Write> Write for ProgressTracker::<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result::<usize> {
+        let written_ = match self.turn.as_ref() {
+            // Tokio's mutex is FIFO, so holding it for a single slice makes concurrent zips round-robin,
+            // which also caps them all together at one core's worth of compression.
+            Some(turn) => {
+                let _turn = turn.blocking_lock();
+                self.writer.write(&buf[..buf.len().min(FAIR_ZIP_SLICE)])?
+            }
+            None => self.writer.write(buf)?
+        };
+        self.written += written_;
+
+        // Blocking already, and after giving up the turn, so that the other zips aren't held back by this one's cap
+        if let Some(wait) = self.throttle.as_ref().map(|throttle| throttle.take(written_)).filter(|wait| !wait.is_zero()) {
+            std::thread::sleep(wait)
+        }
+
+        // Same throttling as the uploads
+        let p = self.progress();
+        if progress_due(self.last_update.map(|(at, _)| at), self.last_update.map_or(0, |(_, last)| last as u8), p as u8, self.step) {
+            self.last_update = Some((Instant::now(), p));
+            let (bps, eta_secs) = self.rate.sample(self.written, self.total_size);
+            let progress_sender = self.progress_sender.lock().unwrap();
+            progress_sender.as_ref().map(|ps| ps.try_send(ProgressEvent { progress: p as _, bps, eta_secs, state: None }));
+        }
+
+        Ok(written_)
+    }
+
+    #[inline(always)]
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[derive(Copy, Clone, Hash, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Compression {
+    // Already compressed media (photos, videos) barely shrinks, so don't burn CPU on it
+    Store,
+    #[default]
+    Deflate,
+    Zstd
+}
+
+impl Compression {
+    #[inline]
+    fn method(self) -> CompressionMethod {
+        match self {
+            Compression::Store   => CompressionMethod::Stored,
+            Compression::Deflate => CompressionMethod::Deflated,
+            Compression::Zstd    => CompressionMethod::Zstd
+        }
+    }
+
+    #[inline]
+    fn levels(self) -> Option::<std::ops::RangeInclusive::<i64>> {
+        match self {
+            Compression::Store   => None,
+            Compression::Deflate => Some(0..=9),
+            Compression::Zstd    => Some(-7..=22)
+        }
+    }
+
+    #[inline]
+    fn name(self) -> &'static str {
+        match self {
+            Compression::Store   => "store",
+            Compression::Deflate => "deflate",
+            Compression::Zstd    => "zstd"
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct DownloadQuery {
+    #[serde(default)]
+    compression: Compression,
+    level: Option::<i64>,
+    label: Option::<String>,
+    // Encrypts the archive with AES-256, `ZIP_PASSWORD_HEADER` keeps it out of the URL. Opening it takes a tool
+    // that knows AES zips, like 7-Zip, the stock `unzip` doesn't.
+    password: Option::<String>
+}
+
+// Deflating at level 8 pegs a weak single-core device for a long time, and so does deflating
+// a huge pile of small files, so pick something cheaper there unless the user asked otherwise.
+fn auto_compression_level(file_count: usize) -> i64 {
+    let cores = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let level = match cores {
+        1     => 1,
+        2..=3 => 4,
+        _     => DEFAULT_COMPRESSION_LEVEL
+    };
+
+    if file_count > 256 { level.min(4) } else { level }
+}
+
+impl DownloadQuery {
+    // `None` means the method has no levels, or zip's default one for it.
+    fn resolve_level(&self, file_count: usize) -> Result::<Option::<i64>, HttpResponse> {
+        let compression = self.compression;
+        match (self.level, compression.levels()) {
+            (Some(level), Some(levels)) if levels.contains(&level) => Ok(Some(level)),
+            (Some(level), Some(levels)) => Err(HttpResponse::BadRequest().body(format!{
+                "invalid {method} level: {level}, expected {start}..={end}",
+                method = compression.name(), start = levels.start(), end = levels.end()
+            })),
+            (Some(_), None) => Err(HttpResponse::BadRequest().body(format!("{method} does not take a level", method = compression.name()))),
+            (None, _) => Ok(matches!(compression, Compression::Deflate).then(|| auto_compression_level(file_count)))
+        }
+    }
+
+    #[inline]
+    fn zip_password(&self, rq: &HttpRequest) -> Option::<String> {
+        let header = rq.headers().get(ZIP_PASSWORD_HEADER).and_then(|header| header.to_str().ok()).map(str::to_owned);
+        header.or_else(|| self.password.clone()).filter(|password| !password.is_empty())
+    }
+
+    // Cloning `File`s only bumps refcounts, so the lock isn't held while compressing.
+    fn select_files(&self, state: &Server) -> Vec::<File> {
+        let label = self.label.as_deref().and_then(sanitize_label);
+        state.lock_files().iter().filter(|f| label.is_none() || f.label == label).cloned().collect()
+    }
+}
+
+// Identifies the archive `download_files` would produce right now: same files, same compression => same bytes.
+fn archive_fingerprint(files: &[File], compression: Compression, level: Option::<i64>) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    compression.hash(&mut hasher);
+    level.hash(&mut hasher);
+    for File { name, size, dir, .. } in files.iter() {
+        dir.hash(&mut hasher);
+        name.hash(&mut hasher);
+        size.hash(&mut hasher)
+    }
+    hasher.finish()
+}
+
+// UTC `(year, month, day, hour, minute, second)` of `secs` since the Unix epoch.
+fn civil_from_secs(secs: u64) -> (i64, u8, u8, u8, u8, u8) {
+    let (days, secs) = ((secs / 86400) as i64, secs % 86400);
+
+    // Howard Hinnant's `civil_from_days`
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (year, month as u8, day as u8, (secs / 3600) as u8, (secs / 60 % 60) as u8, (secs % 60) as u8)
+}
+
+// Zips hold the date and time without a timezone, and the one of whoever extracts it is anybody's guess, so UTC.
+// Anything zips can't hold, like before 1980, becomes their default, 1980-01-01.
+fn zip_date_time(time: SystemTime) -> zip::DateTime {
+    let Ok(since_epoch) = time.duration_since(SystemTime::UNIX_EPOCH) else {
+        return zip::DateTime::default()
+    };
+
+    let (year, month, day, hour, minute, second) = civil_from_secs(since_epoch.as_secs());
+    let Ok(year) = u16::try_from(year) else {
+        return zip::DateTime::default()
+    };
+
+    zip::DateTime::from_date_and_time(year, month, day, hour, minute, second).unwrap_or_default()
+}
+
+// Pasted images come without a name, `paste-20240101-120000.png` says when they were pasted at least.
+fn paste_file_name(time: SystemTime, ext: &str) -> String {
+    let secs = time.duration_since(SystemTime::UNIX_EPOCH).map_or(0, |since_epoch| since_epoch.as_secs());
+    let (year, month, day, hour, minute, second) = civil_from_secs(secs);
+    format!("paste-{year:04}{month:02}{day:02}-{hour:02}{minute:02}{second:02}.{ext}")
+}
+
+#[inline]
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[get("/download-files-mobile")]
+async fn download_files(_: Authorized, rq: HttpRequest, query: Query::<DownloadQuery>, state: Data::<Server>) -> impl Responder {
+    let transfer = match state.start_transfer() {
+        Ok(transfer) => transfer,
+        Err(rsp) => return rsp
+    };
+
+    log::info!("download files requested, zipping them up..");
+
+    let start = Instant::now();
+    let files = query.select_files(&state);
+    let lock_time = start.elapsed();
+
+    zip_files(transfer, files, &query, query.zip_password(&rq), state, (start, lock_time), rq.peer_addr().map(|addr| addr.ip())).await
+}
+
+/// Like `/download-files-mobile`, but zips only the files named in the JSON array body, with the same query string.
+/// Names that aren't there are skipped, listed in the `X-Skipped-Files` header as a JSON array.
+#[post("/download-selected")]
+async fn download_selected(_: Authorized, rq: HttpRequest, query: Query::<DownloadQuery>, names: web::Json::<Vec::<String>>, state: Data::<Server>) -> impl Responder {
+    let transfer = match state.start_transfer() {
+        Ok(transfer) => transfer,
+        Err(rsp) => return rsp
+    };
+
+    log::info!("download of {count} selected files requested, zipping them up..", count = names.len());
+
+    let start = Instant::now();
+    let files = state.lock_files().iter().filter(|f| names.contains(&f.name)).cloned().collect::<Vec::<_>>();
+    let lock_time = start.elapsed();
+
+    let skipped = names.iter().filter(|name| files.iter().all(|f| &f.name != *name)).collect::<Vec::<_>>();
+    if files.is_empty() {
+        return HttpResponse::NotFound().body("none of the selected files are there")
+    }
+
+    let mut rsp = zip_files(transfer, files, &query, query.zip_password(&rq), state, (start, lock_time), rq.peer_addr().map(|addr| addr.ip())).await;
+    if !skipped.is_empty() {
+        log::info!("skipped selected files that aren't there: {skipped:?}");
+
+        // Escaping what isn't ASCII the JSON way, so that any name fits in a header
+        let skipped = serde_json::to_string(&skipped).unwrap().encode_utf16().map(|unit| match unit {
+            0..0x80 => char::from(unit as u8).to_string(),
+            _ => format!("\\u{unit:04x}")
+        }).collect::<String>();
+
+        if let Ok(value) = actix_web::http::header::HeaderValue::from_str(&skipped) {
+            rsp.headers_mut().insert(actix_web::http::header::HeaderName::from_static("x-skipped-files"), value);
+        }
+    }
+
+    rsp
+}
+
+// `transfer` lives until the archive is sent, not just until the response starts.
+// `timing` is when the request started, and how long the files took to get at.
+async fn zip_files(transfer: TransferGuard, files: Vec::<File>, query: &DownloadQuery, password: Option::<String>, state: Data::<Server>, timing: (Instant, std::time::Duration), peer: Option::<IpAddr>) -> HttpResponse {
+    let level = match query.resolve_level(files.len()) {
+        Ok(level) => level,
+        Err(rsp) => return rsp
+    };
+
+    let compression = query.compression;
+    match level {
+        Some(level) => log::info!("using {method} level: {level}", method = compression.name()),
+        None => log::info!("using {method}", method = compression.name())
+    }
+
+    if password.is_some() {
+        log::info!("encrypting the archive with AES-256")
+    }
+
+    // Goes out as it's produced, `tx` is how far behind the zipping can fall when the client is slow to read
+    let (tx, mut rx) = mpsc::channel(8);
+    let compress_start = Instant::now();
+    let zip_checksums = Arc::clone(&state.zip_checksums);
+    actix_rt::task::spawn_blocking(move || {
+        let _ = &transfer;
+        let fingerprint = archive_fingerprint(&files, compression, level);
+        let size = files.iter().map(|f| f.size).sum::<usize>();
+        let len = files.len();
+
+        let zipped = (|| {
+            let opts = SimpleFileOptions::default()
+                .compression_level(level)
+                .compression_method(compression.method());
+
+            let mut opts = match password.as_deref() {
+                Some(password) => opts.with_aes_encryption(AesMode::Aes256, password),
+                None => opts
+            };
+
+            if size > const { GIG * 4 } || len > 65536 {
+                opts = opts.large_file(true)
+            }
+
+            let mut zip = ProgressTracker::new(ZipWriter::new(ZipStream::new(tx.clone())), size, Arc::clone(&state.zipping_progress_sender), state.fair_zip_turn.clone(), state.config.progress_step, state.throttle());
+            zip.writer.set_flush_on_finish_file(true);
+            for File { name, data, modified, dir, .. } in files.iter() {
+                let modified = data.path().and_then(|path| fs::metadata(path).and_then(|meta| meta.modified()).ok()).unwrap_or(*modified);
+                let path = match dir {
+                    Some(dir) => Cow::Owned(format!("{dir}/{name}")),
+                    None => Cow::Borrowed(name.as_str())
+                };
+                zip.writer.start_file(path, opts.last_modified_time(zip_date_time(modified)))?;
+                data.copy_to(&mut zip)?
+            }
+
+            let mut stream = zip.writer.finish().map_err(|e| {
+                std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string())
+            })?;
+            stream.flush()?;
+
+            Ok::<_, std::io::Error>(stream.hasher.finalize())
+        })();
+
+        // Tells the watchers how it ended, it's the last one, so it's worth waiting for room
+        let ended = if zipped.is_ok() { UploadState::Done } else { UploadState::Failed };
+        if let Some(ps) = state.zipping_progress_sender.lock().unwrap().clone() {
+            _ = ps.blocking_send(ProgressEvent { progress: 100, state: Some(ended), ..Default::default() })
+        }
+
+        match zipped {
+            Ok(sha256) => {
+                // Every encrypted archive has its own salt, so no two are the same, nothing to compare against
+                if password.is_none() {
+                    zip_checksums.insert(fingerprint, hex(&sha256));
+                }
+                log::info!("finished zipping up the files in: {secs:.3}s", secs = compress_start.elapsed().as_secs_f64());
+                state.history.record(files.iter().map(|file| history::Entry::new(Direction::Download, file.name.clone(), file.size, peer)).collect())
+            }
+            // Cuts the response short, so that the client doesn't take a broken archive for a complete one
+            Err(e) => {
+                log::error!("error zipping up the files: {e}");
+                _ = tx.blocking_send(Err(e))
+            }
+        }
+    });
+
+    // Waited for, so that a zip that fails right away is a proper 500, and so that `Server-Timing` has something to say
+    // about the compression. Its whole time is only known once the response is long gone, that's logged once it's done.
+    let first = match rx.recv().await {
+        Some(Ok(first)) => first,
+        Some(Err(..)) | None => return HttpResponse::InternalServerError().body("could not zip up the files")
+    };
+
+    // Shows up in the browser's devtools, handy when somebody says that the download is slow.
+    // `compress` and `total` are until the first bytes of the archive were ready, what the client waited for before any came in.
+    let (started, lock_time) = timing;
+    let server_timing = format!{
+        "lock;dur={lock:.3}, compress;dur={compress:.3};desc=\"until first byte\", total;dur={total:.3};desc=\"until first byte\"",
+        lock = lock_time.as_secs_f64() * 1000.0,
+        compress = compress_start.elapsed().as_secs_f64() * 1000.0,
+        total = started.elapsed().as_secs_f64() * 1000.0
+    };
+
+    log::info!("zipping up the files, sending them to your phone as they're ready..");
+    let mut rsp = HttpResponse::Ok();
+    // Already compressed, and gzipping it again would only hold back the chunks.
+    rsp.content_type("application/zip")
+        .insert_header(ContentEncoding::Identity)
+        .append_header(("X-Compression-Method", compression.name()))
+        .append_header(("Server-Timing", server_timing));
+
+    if let Some(level) = level {
+        rsp.append_header(("X-Compression-Level", level.to_string()));
+    }
+
+    rsp.streaming(futures_util::stream::once(future::ready(Ok(first))).chain(ReceiverStream::new(rx)))
+}
+
+// What `ZipWriter` writes, sent out as soon as it's final. With `set_flush_on_finish_file` that's on every flush,
+// since it only seeks back into the file it's writing, to fill in its header. So at most one compressed file
+// is held in memory, not the whole archive.
+struct ZipStream {
+    tx: mpsc::Sender::<std::io::Result::<web::Bytes>>,
+    pending: Vec::<u8>,
+    // Where `pending` starts in the archive
+    flushed: u64,
+    pos: u64,
+    // Of what was sent, for `/download-files-mobile.sha256`
+    hasher: Sha256
+}
+
+impl ZipStream {
+    #[inline(always)]
+    fn new(tx: mpsc::Sender::<std::io::Result::<web::Bytes>>) -> Self {
+        Self { tx, pending: Vec::new(), flushed: 0, pos: 0, hasher: Sha256::new() }
+    }
+}
+
+impl Write for ZipStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result::<usize> {
+        let at = (self.pos - self.flushed) as usize;
+        let overlap = buf.len().min(self.pending.len().saturating_sub(at));
+        self.pending[at..at + overlap].copy_from_slice(&buf[..overlap]);
+        self.pending.extend_from_slice(&buf[overlap..]);
+        self.pos += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result::<()> {
+        if self.pending.is_empty() {
+            return Ok(())
+        }
+
+        let chunk = std::mem::take(&mut self.pending);
+        self.hasher.update(&chunk);
+        self.flushed += chunk.len() as u64;
+
+        self.tx.blocking_send(Ok(chunk.into())).map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::BrokenPipe, "client went away")
+        })
+    }
+}
+
+// Only there because `set_flush_on_finish_file` wants it, for copying files around in the archive, which we don't do
+impl Read for ZipStream {
+    fn read(&mut self, _: &mut [u8]) -> std::io::Result::<usize> {
+        Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "can't read what's being sent"))
+    }
+}
+
+impl Seek for ZipStream {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result::<u64> {
+        let end = self.flushed + self.pending.len() as u64;
+        let pos = match pos {
+            SeekFrom::Start(pos)   => Some(pos),
+            SeekFrom::End(off)     => end.checked_add_signed(off),
+            SeekFrom::Current(off) => self.pos.checked_add_signed(off)
+        };
+
+        match pos {
+            Some(pos) if (self.flushed..=end).contains(&pos) => {
+                self.pos = pos;
+                Ok(pos)
+            }
+            _ => Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "can't seek into what was already sent"))
+        }
+    }
+}
+
+/// SHA-256 of the archive `/download-files-mobile` served for the current set of files.
+///
+/// Fetch it right after the download, with the same query string, and compare it against
+/// a locally computed digest (e.g. `sha256sum droppa.zip`). Responds with 404 if that
+/// archive has not been produced yet, or if the set of files changed in the meantime.
+#[get("/download-files-mobile.sha256")]
+async fn download_files_checksum(_: Authorized, query: Query::<DownloadQuery>, state: Data::<Server>) -> impl Responder {
+    let files = query.select_files(&state);
+    let level = match query.resolve_level(files.len()) {
+        Ok(level) => level,
+        Err(rsp) => return rsp
+    };
+
+    let fingerprint = archive_fingerprint(&files, query.compression, level);
+    match state.zip_checksums.get(&fingerprint) {
+        Some(checksum) => HttpResponse::Ok().content_type("text/plain").body(checksum.clone()),
+        None => HttpResponse::NotFound().body("no archive was produced for the current files, download it first")
+    }
+}
+
+#[derive(Serialize)]
+struct FileEntry {
+    name: String,
+    size: usize,
+    sha256: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    label: Option::<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dir: Option::<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mime: Option::<&'static str>,
+    // How many files `--auto-extract` got out of it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    extracted: Option::<usize>
+}
+
+impl From::<&File> for FileEntry {
+    #[inline]
+    fn from(file: &File) -> Self {
+        Self {
+            name: file.name.clone(),
+            size: file.size,
+            sha256: file.sha256.clone(),
+            label: file.label.clone(),
+            dir: file.dir.clone(),
+            mime: file.mime,
+            extracted: None
+        }
+    }
+}
+
+// What an upload ended up as, the names may differ from what was sent, and HEIC conversion may produce two files.
+#[derive(Serialize)]
+struct Uploaded {
+    files: Vec::<FileEntry>
+}
+
+#[derive(Serialize)]
+struct FileList {
+    count: usize,
+    total_size: usize,
+    files: Vec::<FileEntry>
+}
+
+/// Lists the files that are up for download as `{ count, total_size, files: [{ name, size, sha256, label?, dir?, mime? }] }`,
+/// so that clients can render them and pick what to download. Honors `?label=` like `download_files`.
+#[get("/files")]
+async fn list_files(_: Authorized, query: Query::<DownloadQuery>, state: Data::<Server>) -> impl Responder {
+    let label = query.label.as_deref().and_then(sanitize_label);
+    let files = state.lock_files().iter().filter(|f| label.is_none() || f.label == label).map(FileEntry::from).collect::<Vec::<_>>();
+
+    HttpResponse::Ok().json(FileList {
+        count: files.len(),
+        total_size: files.iter().map(|f| f.size).sum(),
+        files
+    })
+}
+
+// Only the first of several ranges is served, like `NamedFile` does for the files on disk.
+fn memory_file_response(rq: &HttpRequest, bytes: web::Bytes, disposition: ContentDisposition) -> HttpResponse {
+    let mut rsp = HttpResponse::Ok();
+    rsp.content_type("application/octet-stream")
+        .insert_header(ContentEncoding::Identity)
+        .insert_header(("Accept-Ranges", "bytes"))
+        .insert_header(disposition);
+
+    let Some(range) = rq.headers().get("Range").and_then(|header| header.to_str().ok()) else {
+        return rsp.body(bytes)
+    };
+
+    let total = bytes.len();
+    match HttpRange::parse(range, total as u64).as_deref() {
+        Ok([range, ..]) => {
+            let (start, end) = (range.start as usize, (range.start + range.length) as usize);
+            rsp.status(StatusCode::PARTIAL_CONTENT)
+                .insert_header(("Content-Range", format!("bytes {start}-{last}/{total}", last = end - 1)))
+                .body(bytes.slice(start..end))
+        }
+        Ok([]) => rsp.body(bytes),
+        Err(..) => HttpResponse::RangeNotSatisfiable().insert_header(("Content-Range", format!("bytes */{total}"))).finish()
+    }
+}
+
+/// Downloads a single file by its name, for when zipping up everything is overkill.
+/// Honors `Range: bytes=..`, so interrupted downloads can pick up where they stopped.
+#[get("/file/{name}")]
+async fn download_file(_: Authorized, rq: HttpRequest, path: Path::<String>, state: Data::<Server>) -> impl Responder {
+    let _transfer = match state.start_transfer() {
+        Ok(transfer) => transfer,
+        Err(rsp) => return rsp
+    };
+
+    let name = path.into_inner();
+    let Some(file) = state.lock_files().iter().find(|f| f.name == name).cloned() else {
+        return HttpResponse::NotFound().body(format!("no such file: {name}"))
+    };
+
+    let disposition = ContentDisposition::attachment(&file.name);
+    let rsp = match file.data {
+        FileData::Memory(bytes) => memory_file_response(&rq, bytes, disposition),
+        // `NamedFile` does the ranges on its own
+        FileData::Disk(..) | FileData::Saved(..) => match NamedFile::open_async(file.data.path().unwrap()).await {
+            Ok(named) => named.set_content_disposition(disposition).into_response(&rq),
+            Err(e) => {
+                log::error!("could not open: {name}: {e}");
+                HttpResponse::InternalServerError().body(format!("could not open: {name}"))
+            }
+        }
+    };
+
+    // Only whole files, not every range of a resumed download
+    if rsp.status() == StatusCode::OK {
+        state.record_transfers(Direction::Download, std::iter::once((file.name, file.size)), rq.peer_addr().map(|addr| addr.ip()));
+    }
+
+    rsp
+}
+
+/// What was uploaded and downloaded, across restarts too, oldest first, as a JSON array of
+/// `{ timestamp, name, size, direction, peer }`, `direction` being `upload` or `download`.
+#[get("/history")]
+async fn transfer_history(_: Authorized, state: Data::<Server>) -> impl Responder {
+    HttpResponse::Ok().json(state.history.entries())
+}
+
+/// Hex SHA-256 of a single uploaded file, to compare against a locally computed one after the download.
+#[get("/file/{name}/checksum")]
+async fn file_checksum(_: Authorized, path: Path::<String>, state: Data::<Server>) -> impl Responder {
+    let name = path.into_inner();
+    match state.lock_files().iter().find(|f| f.name == name) {
+        Some(file) => HttpResponse::Ok().content_type("text/plain").body(file.sha256.clone()),
+        None => HttpResponse::NotFound().body(format!("no such file: {name}"))
+    }
+}
+
+#[derive(Serialize)]
+struct InlineFile {
+    name: String,
+    size: usize,
+    // Standard base64, with padding
+    data: String
+}
+
+/// A single uploaded file inline, as `{ "name": .., "size": .., "data": "<base64>" }`, for scripts that can't easily deal with a binary download.
+/// Only takes files of up to `DROPPA_BASE64_LIMIT` (1MiB by default), responds with 413 to larger ones, those are for `/file/{name}`.
+#[get("/file/{name}/base64")]
+async fn file_base64(_: Authorized, rq: HttpRequest, path: Path::<String>, state: Data::<Server>) -> impl Responder {
+    let _transfer = match state.start_transfer() {
+        Ok(transfer) => transfer,
+        Err(rsp) => return rsp
+    };
+
+    let name = path.into_inner();
+    let Some(file) = state.lock_files().iter().find(|f| f.name == name).cloned() else {
+        return HttpResponse::NotFound().body(format!("no such file: {name}"))
+    };
+
+    let limit = state.config.base64_limit;
+    if file.size > limit {
+        return HttpResponse::PayloadTooLarge().body(format!("{name} is larger than {limit}, download it from /file/{name} instead", limit = format_size(limit)))
+    }
+
+    let File { name, size, data, .. } = file;
+    let encoded = actix_rt::task::spawn_blocking(move || data.read().map(|bytes| BASE64_STANDARD.encode(bytes))).await;
+    match encoded.map_err(std::io::Error::other).and_then(|r| r) {
+        Ok(data) => {
+            state.record_transfers(Direction::Download, std::iter::once((name.clone(), size)), rq.peer_addr().map(|addr| addr.ip()));
+            HttpResponse::Ok().json(InlineFile { name, size, data })
+        }
+        Err(e) => {
+            log::error!("could not read: {name}: {e}");
+            HttpResponse::InternalServerError().body(format!("could not read: {name}"))
+        }
+    }
+}
+
+/// Removes an uploaded file, so that a mistaken upload doesn't sit in memory until shutdown.
+#[delete("/file/{name}")]
+async fn delete_file(_: Authorized, path: Path::<String>, state: Data::<Server>) -> impl Responder {
+    let name = path.into_inner();
+
+    let removed = {
+        let mut files = state.lock_files();
+        let count = files.len();
+        files.retain(|f| f.name != name);
+        count - files.len()
+    };
+
+    if removed == 0 {
+        return HttpResponse::NotFound().body(format!("no such file: {name}"))
+    }
+
+    // An upload of the same name may be running, its progress stream stays
+    state.clients.remove_if(&name, |_, client| client.cancel.is_none());
+
+    log::info!("deleted: {name}");
+    HttpResponse::Ok().finish()
+}
+
+#[derive(Deserialize)]
+struct Rename {
+    from: String,
+    to: String
+}
+
+#[derive(Serialize)]
+struct Renamed {
+    name: String
+}
+
+/// Renames an uploaded file before it's downloaded, e.g. `IMG_4032.JPG` to something that says what it is.
+/// Responds with the new name, sanitized like uploaded ones are.
+#[post("/rename")]
+async fn rename_file(_: Authorized, rename: web::Json::<Rename>, state: Data::<Server>) -> impl Responder {
+    let Rename { from, to } = rename.into_inner();
+    let to = match sanitize_file_name(&to) {
+        Ok(to) => to,
+        Err(e) => return HttpResponse::BadRequest().body(e)
+    };
+
+    {
+        let mut files = state.lock_files();
+        if from != to && files.iter().any(|f| f.name == to) {
+            return HttpResponse::Conflict().body(format!("there's already a file named: {to}"))
+        }
+
+        let Some(file) = files.iter_mut().find(|f| f.name == from) else {
+            return HttpResponse::NotFound().body(format!("no such file: {from}"))
+        };
+
+        file.name = to.clone()
+    }
+
+    log::info!("renamed: {from} to: {to}");
+    HttpResponse::Ok().json(Renamed { name: to })
+}
+
+#[derive(Serialize)]
+struct Cleared {
+    removed: usize
+}
+
+/// Drops every uploaded file and the progress senders nobody listens to anymore, for a clean slate between transfers.
+#[post("/clear")]
+async fn clear_files(_: Authorized, state: Data::<Server>) -> impl Responder {
+    let removed = {
+        let mut files = state.lock_files();
+        let removed = files.len();
+        files.clear();
+        files.shrink_to_fit();
+        removed
+    };
+
+    // Uploads that are still being watched keep their senders
+    state.clients.retain(|_, client| !client.sender.is_closed());
+    state.zip_checksums.clear();
+
+    log::info!("cleared {removed} files");
+    HttpResponse::Ok().json(Cleared { removed })
+}
+
+#[derive(Serialize, Deserialize)]
+struct Clipboard {
+    text: String
+}
+
+/// Replaces the shared text snippet, either with the body as is, or with `text` of a JSON `{ "text": .. }` body.
+#[post("/clipboard")]
+async fn set_clipboard(_: Authorized, rq: HttpRequest, body: web::Bytes, state: Data::<Server>) -> impl Responder {
+    if body.len() > MAX_CLIPBOARD_LEN {
+        return HttpResponse::PayloadTooLarge().body(format!("snippet exceeds limit of {limit}", limit = format_size(MAX_CLIPBOARD_LEN)))
+    }
+
+    let json = rq.headers().get("Content-Type").and_then(|header| header.to_str().ok()).is_some_and(|ct| ct.starts_with("application/json"));
+    let text = if json {
+        match serde_json::from_slice::<Clipboard>(&body) {
+            Ok(Clipboard { text }) => text,
+            Err(e) => return HttpResponse::BadRequest().body(format!("invalid snippet: {e}"))
+        }
+    } else {
+        match String::from_utf8(body.to_vec()) {
+            Ok(text) => text,
+            Err(_) => return HttpResponse::BadRequest().body("snippet is not valid UTF-8")
+        }
+    };
+
+    log::info!("clipboard updated, {len} bytes", len = text.len());
+
+    // Goes through even if nobody is subscribed, the next `GET /clipboard` still wants it
+    state.clipboard.send_replace(text);
+    HttpResponse::Ok().finish()
+}
+
+#[get("/clipboard")]
+async fn get_clipboard(_: Authorized, state: Data::<Server>) -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/plain; charset=utf-8")
+        .body(state.clipboard.borrow().clone())
+}
+
+/// Streams `{ "text": .. }` with the current snippet right away, and then every time it changes.
+#[get("/clipboard-events")]
+async fn clipboard_events(_: Authorized, state: Data::<Server>) -> impl Responder {
+    let rx = WatchStream::new(state.clipboard.subscribe());
+
+    HttpResponse::Ok()
+        .append_header(("Content-Type", "text/event-stream"))
+        .append_header(("Cache-Control", "no-cache"))
+        .append_header(("Connection", "keep-alive"))
+        .streaming(futures_util::stream::select(rx.map(Some), sse_keepalive()).map(|text| {
+            let event = match text {
+                Some(text) => format!("data: {json}\n\n", json = serde_json::to_string(&Clipboard { text }).unwrap()),
+                None => ": keepalive\n\n".to_owned()
+            };
+            Ok::<_, actix_web::Error>(event.into())
+        }))
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+// I could've used the `FnOnce` and `FnMut` traits here and called different async closures do to different things, //
+// but it seems that this feature is really, really underdeveloped yet.                                             //
 //                                                                                                                  //
 // I've tried to use `Pin` and `Box` and `Future` but it could not help, the compiler was unsatifisfied with the    //
 // lifetimes, because I need to accept the state and the ping_receiver by a reference.                              //
@@ -446,148 +3376,1100 @@ async fn stream_progress(state: Data::<Server>, transmission: Transmission) -> i
 
     {
         let progress_streamer = &mut state.lock_streamer(transmission).await;
-        if progress_streamer.is_some() {
-            progress_streamer.as_ref().unwrap().send("CONNECTION_REPLACED".to_owned()).unwrap();
-            **progress_streamer = Some(ptx);
-            return HttpResponse::Ok()
-                .append_header(("Content-Type", "text/event-stream"))
-                .append_header(("Cache-Control", "no-cache"))
-                .append_header(("Connection", "keep-alive"))
-                .streaming(streamer.map(|data| {
-                    Ok::<_, actix_web::Error>(format!("data: {data}\n\n").into())
-                }))
+        // Dropping the previous sender ends its stream, with a `replaced` event, see `streamer_response`
+        if progress_streamer.replace(ptx).is_some() {
+            return streamer_response(streamer)
+        }
+    }
+
+    // Zipping progress is relayed since startup, see `relay_zipping_progress`
+    if !matches!(transmission, Zipping) {
+        let (tx, mut rx) = mpsc::channel(8);
+        *state.files_progress_pinger.lock().await = Some(tx);
+
+        let state = Data::clone(&state);
+        actix_rt::spawn(async move {
+            loop {
+                if rx.try_recv().is_err() {
+                    tokio_sleep(TokioDuration::from_millis(150)).await;
+                    continue
+                }
+
+                let mobile = matches!(transmission, Mobile);
+                let data = state.clients.iter().filter(|p| p.mobile != mobile).map(|p| {
+                    TrackFile { name: p.key().to_owned(), progress: p.progress, size: p.size }
+                }).collect::<Vec::<_>>();
+
+                let json = serde_json::to_string(&data).unwrap();
+
+                state.streamer_send(json, transmission).await;
+                tokio_sleep(TokioDuration::from_millis(100)).await;
+            }
+        });
+    }
+
+    streamer_response(streamer)
+}
+
+// Unnamed events carry the JSON progress. There's one streamer per `Transmission`, when another client connects
+// the stream ends with a `replaced` event, telling the old one not to reconnect.
+fn streamer_response(streamer: WatchStream::<String>) -> HttpResponse {
+    let events = streamer
+        .map(|data| format!("data: {data}\n\n"))
+        .chain(futures_util::stream::once(future::ready("event: replaced\ndata: {}\n\n".to_owned())));
+
+    HttpResponse::Ok()
+        .append_header(("Content-Type", "text/event-stream"))
+        .append_header(("Cache-Control", "no-cache"))
+        .append_header(("Connection", "keep-alive"))
+        .streaming(events.map(|event| Ok::<_, actix_web::Error>(event.into())))
+}
+
+// Zips report progress whether anybody watches or not, and both the one downloading the archive and the desktop get it.
+async fn relay_zipping_progress(state: Data::<Server>, mut rx: mpsc::Receiver::<ProgressEvent>) {
+    while let Some(progress) = rx.recv().await {
+        if let Some(streamer) = state.zipping_progress_streamer.lock().await.as_ref() {
+            _ = streamer.send(serde_json::to_string(&progress).unwrap())
         }
 
-        **progress_streamer = Some(ptx)
+        // The size of the archive isn't known until it's done
+        let zip = TrackFile { name: ZIP_FILE_NAME.to_owned(), progress: progress.progress, size: 0 };
+        if let Some(streamer) = state.desktop_files_progress_streamer.lock().await.as_ref() {
+            _ = streamer.send(serde_json::to_string(&[zip]).unwrap())
+        }
     }
+}
+
+/// Compact binary progress feed for native (non-browser) clients, multiplexing every transfer over one socket.
+///
+/// Right after the upgrade the server sends a one-byte handshake frame holding `WS_PROGRESS_VERSION`,
+/// clients should drop the connection if they don't speak that version. After that, every progress
+/// change of every tracked file arrives as its own binary frame, integers are big-endian:
+///
+/// ```text
+/// [u16 name_len][name: name_len bytes of UTF-8][u8 progress][u64 bytes received]
+/// ```
+#[get("/ws/progress")]
+async fn ws_progress(_: Authorized, rq: HttpRequest, body: web::Payload, state: Data::<Server>) -> actix_web::Result::<HttpResponse> {
+    let (rsp, mut session, mut msg_stream) = actix_ws::handle(&rq, body)?;
+
+    log::info!("binary progress client connected over websocket");
+
+    let stream_guard = TransferGuard::new(&state.open_streams);
+    actix_rt::spawn(async move {
+        let _ = &stream_guard;
+        if session.binary(vec![WS_PROGRESS_VERSION]).await.is_err() { return }
+
+        let mut last_sent = std::collections::HashMap::<String, (u8, usize)>::new();
+        loop {
+            // Finished transfers are gone from `clients`, a new one of the same name starts over anyway
+            last_sent.retain(|name, _| state.clients.contains_key(name));
+
+            let frames = state.clients.iter().filter_map(|c| {
+                let name = c.key();
+                let name_len = u16::try_from(name.len()).ok()?;
+                if last_sent.get(name) == Some(&(c.progress, c.received)) { return None }
+                last_sent.insert(name.to_owned(), (c.progress, c.received));
+
+                let mut frame = Vec::with_capacity(2 + name.len() + 1 + 8);
+                frame.extend_from_slice(&name_len.to_be_bytes());
+                frame.extend_from_slice(name.as_bytes());
+                frame.push(c.progress);
+                frame.extend_from_slice(&(c.received as u64).to_be_bytes());
+                Some(frame)
+            }).collect::<Vec::<_>>();
+
+            for frame in frames {
+                if session.binary(frame).await.is_err() {
+                    log::info!("binary progress client disconnected");
+                    return
+                }
+            }
+
+            // Polled, there's no single channel all the transfers report to, but a closed socket ends it right away
+            let tick = std::pin::pin!(tokio_sleep(TokioDuration::from_millis(150)));
+            match future::select(tick, msg_stream.next()).await {
+                Either::Left(..) => {}
+                Either::Right((Some(Ok(actix_ws::Message::Ping(bytes))), _)) => {
+                    if session.pong(&bytes).await.is_err() { return }
+                }
+                Either::Right((Some(Ok(actix_ws::Message::Close(_)) | Err(_)) | None, _)) => {
+                    log::info!("binary progress client disconnected");
+                    return
+                }
+                Either::Right((Some(Ok(_)), _)) => {}
+            }
+        }
+    });
+
+    Ok(rsp)
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+enum ProgressAction {
+    Cancel,
+    Pause,
+    Resume
+}
+
+#[derive(Serialize)]
+struct ProgressActionError {
+    error: String
+}
+
+/// `/progress/{file_name}` over a websocket, that also takes control messages. Every change arrives as a text frame
+/// holding the same JSON as the SSE events, the last one has `"state"` being `done`, `failed` or `cancelled`, then
+/// the socket is closed. Sending `{"action":"cancel"}` stops the upload, like `/cancel/{file_name}`, `"pause"` and `"resume"`
+/// work like `/pause/{file_name}` and `/resume/{file_name}`. If that doesn't work the reply is `{"error":"..."}`.
+#[get("/ws/progress/{file_name}")]
+async fn ws_file_progress(_: Authorized, rq: HttpRequest, path: Path::<String>, body: web::Payload, state: Data::<Server>) -> actix_web::Result::<HttpResponse> {
+    let mobile = rq.headers().get("User-Agent").and_then(|header| header.to_str().ok()).is_some_and(user_agent_is_mobile);
+    let (rsp, mut session, mut msg_stream) = actix_ws::handle(&rq, body)?;
+
+    let file_name = path.into_inner();
+    log::info!("client connected to the progress of: {file_name} over websocket");
+
+    let stream_guard = TransferGuard::new(&state.open_streams);
+    let mut rx = state.subscribe_progress(file_name.clone(), mobile);
+
+    actix_rt::spawn(async move {
+        let _ = &stream_guard;
+        let mut last = serde_json::to_string(&*rx.borrow_and_update()).unwrap();
+        if session.text(last.clone()).await.is_err() { return }
 
-    match transmission {
-        Zipping => {
-            let (tx, mut rx) = mpsc::channel(8);
-            *state.zipping_progress_sender.lock().unwrap() = Some(tx);
+        loop {
+            let next = match future::select(std::pin::pin!(rx.changed()), msg_stream.next()).await {
+                Either::Left((changed, _)) => Either::Left(changed.is_ok()),
+                Either::Right((msg, _)) => Either::Right(msg)
+            };
 
-            let state = Data::clone(&state);
-            actix_rt::spawn(async move {
-                loop {
-                    if let Ok(progress) = rx.try_recv() {
-                        let streamer = state.zipping_progress_streamer.lock().await;
-                        let streamer = streamer.as_ref().unwrap();
-                        _ = streamer.send(format!("{{ \"progress\": {progress} }}"));
-                        tokio_sleep(TokioDuration::from_millis(100)).await
-                    } else {
-                        tokio_sleep(TokioDuration::from_millis(150)).await
+            match next {
+                // Also what's sent last, once the client is removed, unless it was sent already
+                Either::Left(changed) => {
+                    let event = serde_json::to_string(&*rx.borrow_and_update()).unwrap();
+                    if event != last {
+                        if session.text(event.clone()).await.is_err() { return }
+                        last = event
+                    }
+                    if !changed {
+                        _ = session.close(None).await;
+                        return
                     }
                 }
-            })
-        }
-        _ => {
-            let (tx, mut rx) = mpsc::channel(8);
-            *state.files_progress_pinger.lock().await = Some(tx);
+                Either::Right(Some(Ok(actix_ws::Message::Text(text)))) => {
+                    let error = match serde_json::from_str::<ProgressAction>(&text) {
+                        Ok(ProgressAction::Cancel) => state.cancel_upload(&file_name).err().map(|(_, e)| e),
+                        Ok(ProgressAction::Pause) => state.pause_upload(&file_name, true).err().map(|(_, e)| e),
+                        Ok(ProgressAction::Resume) => state.pause_upload(&file_name, false).err().map(|(_, e)| e),
+                        Err(e) => Some(format!("invalid action: {e}"))
+                    };
 
-            let state = Data::clone(&state);
-            actix_rt::spawn(async move {
-                loop {
-                    if rx.try_recv().is_err() {
-                        tokio_sleep(TokioDuration::from_millis(150)).await;
-                        continue
+                    if let Some(error) = error {
+                        let error = serde_json::to_string(&ProgressActionError { error }).unwrap();
+                        if session.text(error).await.is_err() { return }
                     }
-
-                    let mobile = matches!(transmission, Mobile);
-                    let data = state.clients.iter().filter(|p| p.mobile != mobile).map(|p| {
-                        TrackFile { name: p.key().to_owned(), progress: p.progress, size: p.size }
-                    }).collect::<Vec::<_>>();
-
-                    let json = serde_json::to_string(&data).unwrap();
-
-                    state.streamer_send(json, transmission).await;
-                    tokio_sleep(TokioDuration::from_millis(100)).await;
                 }
-            })
+                Either::Right(Some(Ok(actix_ws::Message::Ping(bytes)))) => {
+                    if session.pong(&bytes).await.is_err() { return }
+                }
+                Either::Right(Some(Ok(actix_ws::Message::Close(_)) | Err(_)) | None) => {
+                    log::info!("progress client of: {file_name} disconnected from websocket");
+                    return
+                }
+                Either::Right(Some(Ok(_))) => {}
+            }
         }
-    };
+    });
 
-    HttpResponse::Ok()
-        .append_header(("Content-Type", "text/event-stream"))
-        .append_header(("Cache-Control", "no-cache"))
-        .append_header(("Connection", "keep-alive"))
-        .streaming(streamer.map(|data| {
-            Ok::<_, actix_web::Error>(format!("data: {data}\n\n").into())
-        }))
+    Ok(rsp)
 }
 
 #[get("/download-files-progress-mobile")]
-async fn download_files_progress_mobile(state: Data::<Server>) -> impl Responder {
+async fn download_files_progress_mobile(_: Authorized, state: Data::<Server>) -> impl Responder {
     stream_progress(state, Transmission::Mobile).await
 }
 
 #[get("/download-files-progress-desktop")]
-async fn download_files_progress_desktop(state: Data::<Server>) -> impl Responder {
+async fn download_files_progress_desktop(_: Authorized, state: Data::<Server>) -> impl Responder {
     stream_progress(state, Transmission::Desktop).await
 }
 
 #[get("/zipping-progress")]
-async fn zipping_progress(state: Data::<Server>) -> impl Responder {
+async fn zipping_progress(_: Authorized, state: Data::<Server>) -> impl Responder {
     stream_progress(state, Transmission::Zipping).await
 }
 
-fn get_default_local_ip_addr() -> Option::<IpAddr> {
-    let sock = UdpSocket::bind("0.0.0.0:0").ok()?;
-    sock.connect("1.1.1.1:80").ok()?;
+#[inline]
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// Polls until every counter drops to zero, returns false if `timeout` passes first.
+async fn wait_until_idle(counters: &[&AtomicUsize], timeout: TokioDuration) -> bool {
+    let deadline = Instant::now() + timeout;
+    while counters.iter().any(|c| c.load(Ordering::SeqCst) != 0) {
+        if Instant::now() >= deadline {
+            return false
+        }
+
+        tokio_sleep(TokioDuration::from_millis(250)).await
+    }
+    true
+}
+
+#[derive(Serialize)]
+struct Status {
+    uptime_secs: u64,
+    listen_addr: SocketAddr,
+
+    // Buffered files, what `/files` lists
+    files: usize,
+    total_size: usize,
+
+    // Uploads somebody watches the progress of
+    progress_clients: usize,
+    // Progress streams that are open right now, there's one per connected page or so
+    open_streams: usize,
+
+    active_transfers: usize,
+    active_uploads: usize,
+    queued_uploads: usize,
+    max_uploads: usize
+}
+
+/// What the server is up to, for dashboards and liveness probes.
+#[get("/status")]
+async fn server_status(state: Data::<Server>) -> impl Responder {
+    let (files, total_size) = {
+        let files = state.lock_files();
+        (files.len(), files.iter().map(|f| f.size).sum())
+    };
+
+    HttpResponse::Ok().json(Status {
+        uptime_secs: state.started.elapsed().as_secs(),
+        listen_addr: state.listen_addr,
+        files,
+        total_size,
+        progress_clients: state.clients.len(),
+        open_streams: state.open_streams.load(Ordering::SeqCst),
+        active_transfers: state.active_transfers.load(Ordering::SeqCst),
+        active_uploads: state.config.max_uploads - state.upload_slots.available_permits(),
+        queued_uploads: state.queued_uploads.load(Ordering::SeqCst),
+        max_uploads: state.config.max_uploads
+    })
+}
+
+#[derive(Serialize)]
+struct DrainStatus {
+    active_transfers: usize,
+    open_streams: usize
+}
+
+/// Stops accepting new transfers (they get 503), waits for the running ones and the open progress
+/// streams to finish, up to `DROPPA_DRAIN_TIMEOUT` seconds, then shuts the server down gracefully.
+/// Requires `DROPPA_ADMIN_PIN` to be set, and the same PIN in the `X-Admin-Pin` header.
+#[post("/admin/drain")]
+async fn admin_drain(rq: HttpRequest, state: Data::<Server>) -> impl Responder {
+    let Some(admin_pin) = state.config.admin_pin.as_ref() else {
+        return HttpResponse::NotFound().body("admin endpoints are disabled, set `DROPPA_ADMIN_PIN` to enable them")
+    };
+
+    let pin = rq.headers().get("X-Admin-Pin").map(|h| h.as_bytes()).unwrap_or_default();
+    if !constant_time_eq(pin, admin_pin.as_bytes()) {
+        return HttpResponse::Unauthorized().body("invalid admin PIN")
+    }
+
+    let status = DrainStatus {
+        active_transfers: state.active_transfers.load(Ordering::SeqCst),
+        open_streams: state.open_streams.load(Ordering::SeqCst)
+    };
+
+    if state.draining.swap(true, Ordering::SeqCst) {
+        return HttpResponse::Accepted().json(status)
+    }
+
+    let timeout = state.config.drain_timeout;
+    log::info!("draining: waiting for {n} transfers and {m} streams, for up to {timeout}s..", n = status.active_transfers, m = status.open_streams, timeout = timeout.as_secs());
+
+    let state = Data::clone(&state);
+    actix_rt::spawn(async move {
+        if wait_until_idle(&[&state.active_transfers, &state.open_streams], timeout).await {
+            log::info!("drained, shutting down..");
+        } else {
+            let transfers = state.active_transfers.load(Ordering::SeqCst);
+            let streams = state.open_streams.load(Ordering::SeqCst);
+            log::warn!("drain timed out with {transfers} transfers and {streams} streams still running, shutting down anyway..");
+        }
+
+        state.shutdown.notify_one()
+    });
+
+    HttpResponse::Accepted().json(status)
+}
+
+async fn termination_signal() -> &'static str {
+    #[cfg(unix)]
+    if let Ok(mut terminate) = signal::unix::signal(signal::unix::SignalKind::terminate()) {
+        let ctrl_c = std::pin::pin!(signal::ctrl_c());
+        let terminate = std::pin::pin!(terminate.recv());
+        return match future::select(ctrl_c, terminate).await {
+            Either::Left(..)  => "SIGINT",
+            Either::Right(..) => "SIGTERM"
+        }
+    }
+
+    _ = signal::ctrl_c().await;
+    "SIGINT"
+}
+
+// Tabs that subscribed to an upload and were closed before it started leave their client behind, running uploads keep theirs.
+async fn sweep_clients(clients: AtomicClients) {
+    loop {
+        tokio_sleep(TokioDuration::from_secs(CLIENT_SWEEP_SECS)).await;
+
+        let count = clients.len();
+        clients.retain(|_, client| client.cancel.is_some() || !client.sender.is_closed());
+        if count != clients.len() {
+            log::debug!("swept {swept} progress clients nobody watches", swept = count - clients.len())
+        }
+    }
+}
+
+// Off unless `--cors`, browsers then keep other origins out on their own. Custom headers like `X-Droppa-Token`
+// make browsers send a preflight, and the ones we answer with, like `X-Skipped-Files`, are hidden from scripts unless exposed.
+fn cors(origins: &[String]) -> Cors {
+    let cors = Cors::default().allow_any_method().allow_any_header().expose_any_header().max_age(3600);
+    origins.iter().fold(cors, |cors, origin| match origin.as_str() {
+        "*" => cors.allow_any_origin(),
+        _ => cors.allowed_origin(origin)
+    })
+}
+
+// Running transfers count as activity, so the countdown only starts once the last one is done.
+async fn shutdown_when_idle(state: Data::<Server>, timeout: TokioDuration) {
+    let mut warned = None;
+    loop {
+        tokio_sleep(TokioDuration::from_secs(1)).await;
+
+        if state.active_transfers.load(Ordering::SeqCst) > 0 {
+            state.touch()
+        }
+
+        let idle = state.lock_last_activity().elapsed();
+        let Some(left) = timeout.checked_sub(idle).filter(|left| !left.is_zero()) else {
+            break
+        };
+
+        // Every 10 seconds of the last 30, then every second of the last 5
+        let secs = left.as_secs_f64().ceil() as u64;
+        if secs <= IDLE_WARNING_SECS && (secs.is_multiple_of(10) || secs <= 5) && warned != Some(secs) {
+            log::warn!("nothing happened for {idle}s, shutting down in {secs}s..", idle = idle.as_secs());
+            warned = Some(secs)
+        }
+    }
+
+    log::info!("nothing happened for {timeout}s, shutting down..", timeout = timeout.as_secs());
+    state.draining.store(true, Ordering::SeqCst);
+    state.shutdown.notify_one()
+}
+
+// Actix's own signal handling would cut off the uploads that are still being written to `downloads_dir`,
+// so on SIGINT/SIGTERM stop taking new transfers and let the running ones finish first, like `/admin/drain`.
+// Progress streams aren't waited for, a page that's merely open would hold the shutdown up for no reason.
+async fn shutdown_on_signal(state: Data::<Server>) {
+    let signal = termination_signal().await;
+
+    state.draining.store(true, Ordering::SeqCst);
+    let in_flight = state.active_transfers.load(Ordering::SeqCst);
+    let timeout = state.config.drain_timeout;
+    log::info!("got {signal}, waiting for {in_flight} transfers to finish, for up to {timeout}s, send it again to stop right away..", timeout = timeout.as_secs());
+
+    let transfers = [&*state.active_transfers];
+    let idle = std::pin::pin!(wait_until_idle(&transfers, timeout));
+    let again = std::pin::pin!(termination_signal());
+    if let Either::Right(..) = future::select(idle, again).await {
+        log::warn!("got another signal, not waiting any longer..")
+    }
+
+    let aborted = state.active_transfers.load(Ordering::SeqCst);
+    log::info!("{completed} transfers completed, {aborted} aborted, shutting down..", completed = in_flight.saturating_sub(aborted));
+
+    state.shutdown.notify_one()
+}
+
+// Whatever is left was cut off mid-write, a truncated file is worse than no file.
+fn remove_partial_writes(state: &Server) {
+    for path in state.partial_writes.iter() {
+        match fs::remove_file(path.key()) {
+            Ok(()) => log::info!("removed partially written: {path}", path = path.display()),
+            Err(e) => log::error!("could not remove partially written: {path}: {e}", path = path.display())
+        }
+    }
+
+    if let Some(dir) = state.spill_dir.as_ref() {
+        if let Err(e) = fs::remove_dir_all(dir) {
+            log::error!("could not remove temporary files in: {dir}: {e}", dir = dir.display())
+        }
+    }
+}
+
+// Mobile browsers will complain about the self-signed certificate, so the fingerprint rides along to compare against.
+// The password does too, so that scanning the code is all it takes to get in.
+fn server_url(ip: IpAddr, port: u16, tls_fingerprint: Option::<&[u8; 32]>, password: Option::<&str>) -> String {
+    let mut query = Vec::new();
+    if let Some(fingerprint) = tls_fingerprint {
+        query.push(format!("fp={fingerprint}", fingerprint = hex(fingerprint)))
+    }
+    if let Some(password) = password {
+        query.push(format!("token={password}", password = percent_encode(password)))
+    }
+
+    // `SocketAddr` puts IPv6 addresses in brackets, as URLs want them
+    let addr = SocketAddr::new(ip, port);
+    let scheme = if tls_fingerprint.is_some() { "https" } else { "http" };
+    if query.is_empty() {
+        format!("{scheme}://{addr}")
+    } else {
+        format!("{scheme}://{addr}/?{query}", query = query.join("&"))
+    }
+}
+
+// What phones' cameras take as "join this network", `\`, `;`, `,`, `:` and `"` are escaped with a backslash.
+fn wifi_qr_text(ssid: &str, password: Option::<&str>) -> String {
+    let escape = |s: &str| s.chars().fold(String::with_capacity(s.len()), |mut escaped, c| {
+        if matches!(c, '\\' | ';' | ',' | ':' | '"') {
+            escaped.push('\\')
+        }
+        escaped.push(c);
+        escaped
+    });
+
+    match password {
+        Some(password) => format!("WIFI:S:{ssid};T:WPA;P:{password};;", ssid = escape(ssid), password = escape(password)),
+        None => format!("WIFI:S:{ssid};T:nopass;;", ssid = escape(ssid))
+    }
+}
+
+// `Logger`'s default `%r`, with the passwords in the query string blanked out
+fn redacted_request_line(rq: &actix_web::dev::ServiceRequest) -> String {
+    let path = rq.path();
+    let query = rq.query_string().split('&').map(|pair| match pair.split_once('=') {
+        Some((key @ ("token" | "password"), _)) => format!("{key}=<redacted>"),
+        _ => pair.to_owned()
+    }).collect::<Vec::<_>>().join("&");
+
+    let method = rq.method();
+    let version = rq.version();
+    if query.is_empty() {
+        format!("{method} {path} {version:?}")
+    } else {
+        format!("{method} {path}?{query} {version:?}")
+    }
+}
+
+fn percent_encode(s: &str) -> String {
+    s.bytes().map(|b| match b {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+        _ => format!("%{b:02X}")
+    }).collect()
+}
+
+// Nothing is sent, connecting a UDP socket only picks the address the route to the resolver goes out of.
+fn get_default_local_ip_addr(prefer_ipv6: bool) -> Option::<IpAddr> {
+    let v4 = || local_ip_addr_towards("0.0.0.0:0", "1.1.1.1:80");
+    let v6 = || local_ip_addr_towards("[::]:0", "[2606:4700:4700::1111]:80");
+    if prefer_ipv6 {
+        v6().or_else(v4)
+    } else {
+        v4().or_else(v6)
+    }
+}
+
+fn local_ip_addr_towards(bind: &str, resolver: &str) -> Option::<IpAddr> {
+    let sock = UdpSocket::bind(bind).ok()?;
+    sock.connect(resolver).ok()?;
     sock.local_addr().ok().map(|addr| addr.ip())
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
+    let args = Args::parse();
+
     env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
 
-    println!("[INFO] looking for default local IP address...");
-    let local_ip = get_default_local_ip_addr().unwrap_or_else(|| panic!("could not find local IP address"));
+    let config = Config::load(&args);
+
+    #[cfg(not(feature = "heic"))]
+    if config.convert_heic {
+        log::warn!("`DROPPA_CONVERT_HEIC` is set, but droppa was built without the `heic` feature, ignoring it")
+    }
+
+    let cors_origins = args.cors_origins();
+    let local_ip = match config.bind_ip {
+        Some(ip) => {
+            log::info!("using: {ip} to generate QR code...");
+            ip
+        }
+        None => {
+            log::info!("looking for default local IP address...");
+            // No default route, like in containers or offline, still serve locally. We keep listening on all addresses,
+            // so once a network comes up, `/regenerate-qr` makes the QR code usable.
+            match get_default_local_ip_addr(config.prefer_ipv6) {
+                Some(ip) => {
+                    log::info!("found: {ip}, using it to generate QR code...");
+                    ip
+                }
+                None => {
+                    let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+                    log::warn!("could not find local IP address, falling back to: {ip}");
+                    log::warn!("the QR code will only work on this machine, phones won't be able to reach it");
+                    ip
+                }
+            }
+        }
+    };
+
+    let tls = config.tls.then(|| {
+        let tls = tls::self_signed(local_ip).unwrap_or_else(|e| panic!("could not generate TLS certificate: {e}"));
+        log::info!("generated a self-signed TLS certificate, make sure your browser shows this SHA-256 fingerprint:");
+        log::info!("{fingerprint}", fingerprint = tls.fingerprint_pretty());
+        tls
+    });
+
+    if config.password.is_some() {
+        log::info!("password protection is on, the QR code lets you in")
+    }
+
+    let local_addr = server_url(local_ip, config.port, tls.as_ref().map(|tls| &tls.fingerprint), config.password.as_deref());
+    let qr = QrCode::encode_text(&local_addr, args.qr_ecc()).expect("could not encode URL to QR code");
+
+    let qr_bytes = web::Bytes::from(gen_qr_png_bytes(&qr).expect("could not generate QR code image"));
 
-    println!("[INFO] found: {local_ip}, using it to generate QR code...");
-    let local_addr = format!("http://{local_ip}:{PORT}");
-    let qr = QrCode::encode_text(&local_addr, QrCodeEcc::Low).expect("could not encode URL to QR code");
+    let wifi_qr_bytes = args.wifi().map(|(ssid, password)| {
+        let qr = QrCode::encode_text(&wifi_qr_text(&ssid, password.as_deref()), args.qr_ecc()).expect("could not encode Wi-Fi credentials to QR code");
+        log::info!("serving a QR code that joins: {ssid} at `/wifi-qr.png`");
+        web::Bytes::from(gen_qr_png_bytes(&qr).expect("could not generate Wi-Fi QR code image"))
+    });
+
+    // The QR code is still served at `/qr.png`, so a bad path isn't worth refusing to start over
+    if let Some(path) = args.qr_out.as_ref() {
+        match fs::write(path, &qr_bytes) {
+            Ok(()) => log::info!("saved QR code to: {path}", path = path.display()),
+            Err(e) => log::error!("could not save QR code to: {path}: {e}", path = path.display())
+        }
+    }
+
+    fs::create_dir_all(&config.downloads_dir).unwrap_or_else(|e| panic!("could not create downloads directory: {dir}: {e}", dir = config.downloads_dir.display()));
+    log::info!("saving files to: {dir}", dir = config.downloads_dir.display());
+
+    // Still served, desktop uploads kept in memory and downloads work without it
+    if let Err(e) = check_writable(&config.downloads_dir) {
+        log::error!("could not write to the downloads directory: {dir}: {e}", dir = config.downloads_dir.display());
+        log::error!("uploads from phones will fail until it's writable, pass a different one with `--download-dir`");
+    }
+
+    log::info!("upload size limit: {limit}", limit = format_size(config.size_limit));
+    log::info!("running up to {max_uploads} uploads at once", max_uploads = config.max_uploads);
 
+    if !config.spill_to_disk {
+        log::info!("desktop uploads may take up to: {budget} of RAM", budget = format_size(config.memory_budget))
+    }
+
+    if let Some(rate) = config.max_rate {
+        let shared = if config.max_rate_per_transfer { "each transfer" } else { "all transfers together" };
+        log::info!("transfers capped at: {rate}/s, {shared}", rate = format_size(rate))
+    }
+
+    // An autodetected address may change under us, so listen on all of them for `/regenerate-qr` to be of any use
+    let listen_ip = config.bind_ip.unwrap_or(match local_ip {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+    });
+
+    let (zipping_tx, zipping_rx) = mpsc::channel(8);
     let server = Data::new(Server {
-        qr_bytes: gen_qr_png_bytes(&qr).expect("could not generate QR code image").into(),
+        listen_addr: SocketAddr::new(listen_ip, config.port),
+        started: Instant::now(),
+        memory_budget: MemoryBudget::new(config.memory_budget),
+        qr_bytes: Arc::new(Mutex::new(qr_bytes)),
+        qr_target: Arc::new(Mutex::new(ServerInfo { url: local_addr.clone(), ip: local_ip, port: config.port })),
+        qr_ecc: args.qr_ecc(),
+        wifi_qr_bytes,
+        tls_fingerprint: tls.as_ref().map(|tls| tls.fingerprint),
 
-        downloads_dir: {
-            let mut dir = dirs::download_dir().expect("could not get user's `Downloads` directory");
-            dir.push(DROPPA_DOWNLOADS_DIR);
+        spill_dir: config.spill_to_disk.then(|| {
+            let dir = config.downloads_dir.join(SPILL_DIR);
 
-            if !dir.exists() {
-                fs::create_dir(&dir).expect("could not create `droppa` downloads sub-directory")
+            // Whatever is in there was left behind by a previous run that didn't get to clean up
+            if dir.exists() {
+                if let Err(e) = fs::remove_dir_all(&dir) {
+                    log::warn!("could not remove stale temporary files in: {dir}: {e}", dir = dir.display())
+                }
             }
 
+            fs::create_dir_all(&dir).expect("could not create directory for temporary files");
+            log::info!("desktop uploads will be kept in: {dir}", dir = dir.display());
             dir
-        },
+        }),
 
         files: Arc::new(Mutex::new(Vec::new())),
         clients: Arc::new(DashMap::new()),
 
+        zip_checksums: Arc::new(DashMap::new()),
+        chunked_uploads: Arc::new(DashMap::new()),
+        resumable_uploads: Arc::new(DashMap::new()),
+
+        clipboard: watch::channel(String::new()).0,
+
+        #[cfg(feature = "heic")]
+        heic_conversion: config.convert_heic.then(|| {
+            log::info!("HEIC uploads will be converted to JPEG");
+            heic::Conversion {
+                keep_original: config.keep_heic
+            }
+        }),
+
+        fair_zip_turn: config.fair_downloads.then(|| {
+            log::info!("fair downloads are enabled, concurrent zips will take turns");
+            Arc::new(TokioMutex::new(()))
+        }),
+
         files_progress_pinger: Arc::new(TokioMutex::new(None)),
 
-        zipping_progress_sender: Arc::new(Mutex::new(None)),
+        upload_rate_limiter: if config.upload_rate == 0.0 {
+            log::info!("upload rate limiting is off");
+            None
+        } else {
+            log::info!("accepting up to {rate} uploads per second per client", rate = config.upload_rate);
+            Some(RateLimiter::new(config.upload_rate))
+        },
+
+        shared_throttle: config.max_rate.filter(|_| !config.max_rate_per_transfer).map(|rate| Arc::new(Throttle::new(rate))),
+
+        upload_slots: Arc::new(Semaphore::new(config.max_uploads)),
+        queued_uploads: Arc::new(AtomicUsize::new(0)),
+
+        draining: AtomicBool::new(false),
+        shutdown: Arc::new(Notify::new()),
+        open_streams: Arc::new(AtomicUsize::new(0)),
+        active_transfers: Arc::new(AtomicUsize::new(0)),
+        last_activity: Arc::new(Mutex::new(Instant::now())),
+
+        partial_writes: Arc::new(DashSet::new()),
+        append_locks: Arc::new(DashMap::new()),
+
+        history: Arc::new(History::load(&config.downloads_dir)),
+
+        zipping_progress_sender: Arc::new(Mutex::new(Some(zipping_tx))),
 
         zipping_progress_streamer: Arc::new(TokioMutex::new(None)),
         mobile_files_progress_streamer: Arc::new(TokioMutex::new(None)),
         desktop_files_progress_streamer: Arc::new(TokioMutex::new(None)),
+
+        // Moved in last, the fields above still read from it
+        config
     });
 
-    println!("[INFO] serving at: <http://{local_ip}:{PORT}>");
+    log::info!("serving at: <{local_addr}>");
+    if args.qr_terminal {
+        print_qr_to_terminal(&qr)
+    }
+
+    actix_rt::spawn(relay_zipping_progress(Data::clone(&server), zipping_rx));
 
-    HttpServer::new(move || {
+    let shutdown = Arc::clone(&server.shutdown);
+    actix_rt::spawn(shutdown_on_signal(Data::clone(&server)));
+    actix_rt::spawn(sweep_clients(Arc::clone(&server.clients)));
+    if let Some(timeout) = args.idle_timeout() {
+        log::info!("shutting down after {timeout}s without any activity", timeout = timeout.as_secs());
+        actix_rt::spawn(shutdown_when_idle(Data::clone(&server), timeout));
+    }
+
+    if let Some(origins) = cors_origins.as_ref() {
+        log::info!("allowing cross-origin requests from: {origins}", origins = origins.join(", "))
+    }
+
+    let state = Data::clone(&server);
+    let assets_dir = state.config.assets_dir.clone();
+    if let Some(dir) = assets_dir.as_ref() {
+        log::info!("serving pages and scripts from: {dir}, where it has them", dir = dir.display())
+    }
+
+    let http_server = HttpServer::new(move || {
         App::new()
             .app_data(Data::clone(&server))
-            .wrap(Logger::default())
+            .wrap(Condition::new(cors_origins.is_some(), cors(cors_origins.as_deref().unwrap_or_default())))
+            .wrap(Logger::new(r#"%a "%{r}xi" %s %b "%{Referer}i" "%{User-Agent}i" %T"#).custom_request_replace("r", redacted_request_line))
+            .wrap(Compress::default())
             .service(index)
+            .service(favicon)
             .service(qr_code)
+            .service(wifi_qr_code)
+            .service(regenerate_qr)
+            .service(server_info)
             .service(upload_mobile)
             .service(upload_desktop)
+            .service(upload_paste)
+            .service(upload_chunk)
+            .service(upload_check)
+            .service(append_file)
+            .service(tail_file)
+            .service(upload_status)
             .service(track_progress)
+            .service(cancel_upload)
+            .service(pause_upload)
+            .service(resume_upload)
             .service(download_files)
+            .service(download_files_checksum)
+            .service(download_selected)
+            .service(list_files)
+            .service(download_file)
+            .service(file_checksum)
+            .service(file_base64)
+            .service(transfer_history)
+            .service(delete_file)
+            .service(rename_file)
+            .service(clear_files)
+            .service(set_clipboard)
+            .service(get_clipboard)
+            .service(clipboard_events)
             .service(zipping_progress)
+            .service(ws_progress)
+            .service(ws_file_progress)
             .service(download_files_progress_mobile)
             .service(download_files_progress_desktop)
-            .service(ActixFiles::new("/", "./front"))
-    }).bind((local_ip.to_string(), PORT))?.run().await
+            .service(server_status)
+            .service(admin_drain)
+            // Whatever `--assets-dir` doesn't have comes from the bundled ones
+            .service(match assets_dir.as_ref() {
+                Some(dir) => ActixFiles::new("/", dir).default_handler(ActixFiles::new("/", "./front").default_handler(web::to(not_found))),
+                None => ActixFiles::new("/", "./front").default_handler(web::to(not_found))
+            })
+            .default_service(web::to(not_found))
+    });
+
+    let http_server = match tls {
+        Some(tls) => http_server.bind_rustls_0_23((listen_ip, state.config.port), tls.config)?,
+        None => http_server.bind((listen_ip, state.config.port))?
+    };
+
+    let http_server = http_server
+        .disable_signals()
+        // The transfers were already waited for by the time we stop, only idle connections are left
+        .shutdown_timeout(1)
+        .run();
+
+    let handle = http_server.handle();
+    actix_rt::spawn(async move {
+        shutdown.notified().await;
+        handle.stop(true).await
+    });
+
+    let advertisement = if args.mdns {
+        match mdns::advertise(local_ip, state.config.port) {
+            Ok(advertisement) => {
+                log::info!("advertising: {name} over mDNS, also reachable at: <{scheme}://droppa.local:{port}>", port = state.config.port, name = advertisement.fullname(), scheme = if state.config.tls { "https" } else { "http" });
+                Some(advertisement)
+            }
+            Err(e) => {
+                log::warn!("could not advertise over mDNS: {e}, the network may be blocking multicast");
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    http_server.await?;
+
+    if let Some(advertisement) = advertisement {
+        advertisement.stop()
+    }
+
+    remove_partial_writes(&state);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::header::CONTENT_TYPE, test as actix_test};
+
+    const BOUNDARY: &str = "droppa-test-boundary";
+
+    macro_rules! app {
+        ($state: expr) => {
+            actix_test::init_service(App::new().app_data(Data::clone(&$state)).service(upload_desktop).service(upload_mobile).service(append_file).service(download_files).service(track_progress)).await
+        };
+    }
+
+    // A `Server` the way `main` builds it, minus the QR codes, TLS and the progress relay
+    fn server(name: &str, config: Config) -> Data::<Server> {
+        let downloads_dir = std::env::temp_dir().join(format!("droppa-test-{name}-{pid}", pid = std::process::id()));
+        _ = fs::remove_dir_all(&downloads_dir);
+        fs::create_dir_all(&downloads_dir).unwrap();
+
+        let config = Config { downloads_dir, ..config };
+        Data::new(Server {
+            listen_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), config.port),
+            started: Instant::now(),
+            memory_budget: MemoryBudget::new(config.memory_budget),
+            qr_bytes: Arc::new(Mutex::new(web::Bytes::new())),
+            qr_target: Arc::new(Mutex::new(ServerInfo { url: String::new(), ip: IpAddr::V4(Ipv4Addr::LOCALHOST), port: config.port })),
+            qr_ecc: QrCodeEcc::Low,
+            wifi_qr_bytes: None,
+            tls_fingerprint: None,
+            spill_dir: None,
+            files: Arc::new(Mutex::new(Vec::new())),
+            clients: Arc::new(DashMap::new()),
+            zip_checksums: Arc::new(DashMap::new()),
+            chunked_uploads: Arc::new(DashMap::new()),
+            resumable_uploads: Arc::new(DashMap::new()),
+            clipboard: watch::channel(String::new()).0,
+            #[cfg(feature = "heic")]
+            heic_conversion: None,
+            fair_zip_turn: None,
+            files_progress_pinger: Arc::new(TokioMutex::new(None)),
+            upload_rate_limiter: None,
+            shared_throttle: None,
+            upload_slots: Arc::new(Semaphore::new(config.max_uploads)),
+            queued_uploads: Arc::new(AtomicUsize::new(0)),
+            draining: AtomicBool::new(false),
+            shutdown: Arc::new(Notify::new()),
+            open_streams: Arc::new(AtomicUsize::new(0)),
+            active_transfers: Arc::new(AtomicUsize::new(0)),
+            last_activity: Arc::new(Mutex::new(Instant::now())),
+            partial_writes: Arc::new(DashSet::new()),
+            append_locks: Arc::new(DashMap::new()),
+            history: Arc::new(History::load(&config.downloads_dir)),
+            zipping_progress_sender: Arc::new(Mutex::new(None)),
+            zipping_progress_streamer: Arc::new(TokioMutex::new(None)),
+            mobile_files_progress_streamer: Arc::new(TokioMutex::new(None)),
+            desktop_files_progress_streamer: Arc::new(TokioMutex::new(None)),
+            config
+        })
+    }
+
+    // A `multipart/form-data` body with a `size` field ahead of the file, when it's given, like the desktop page sends it
+    fn multipart(name: &str, bytes: &[u8], size: Option::<usize>) -> Vec::<u8> {
+        let mut body = Vec::new();
+        if let Some(size) = size {
+            body.extend(format!("--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"size\"\r\n\r\n{size}\r\n").as_bytes())
+        }
+        body.extend(format!("--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{name}\"\r\nContent-Type: application/octet-stream\r\n\r\n").as_bytes());
+        body.extend(bytes);
+        body.extend(format!("\r\n--{BOUNDARY}--\r\n").as_bytes());
+        body
+    }
+
+    fn upload(uri: &str, name: &str, bytes: &[u8], size: Option::<usize>) -> actix_test::TestRequest {
+        actix_test::TestRequest::post()
+            .uri(uri)
+            .insert_header((CONTENT_TYPE, format!("multipart/form-data; boundary={BOUNDARY}")))
+            .set_payload(multipart(name, bytes, size))
+    }
+
+    #[actix_web::test]
+    async fn uploaded_files_come_back_zipped() {
+        let state = server("roundtrip", Config::default());
+        let app = app!(state);
+
+        let bytes = (0..10_000).map(|i| (i % 251) as u8).collect::<Vec::<_>>();
+        let rsp = actix_test::call_service(&app, upload("/upload-desktop", "a.bin", &bytes, Some(bytes.len())).to_request()).await;
+        assert_eq!(rsp.status(), StatusCode::OK);
+
+        let rsp = actix_test::call_service(&app, actix_test::TestRequest::get().uri("/download-files-mobile").to_request()).await;
+        assert_eq!(rsp.status(), StatusCode::OK);
+
+        let zipped = actix_test::read_body(rsp).await;
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(zipped)).unwrap();
+        assert_eq!(archive.len(), 1);
+
+        let mut entry = archive.by_name("a.bin").unwrap();
+        let mut unzipped = Vec::new();
+        entry.read_to_end(&mut unzipped).unwrap();
+        assert_eq!(unzipped, bytes);
+    }
+
+    #[actix_web::test]
+    async fn upload_without_size_field_is_stored() {
+        let state = server("no-size", Config::default());
+        let app = app!(state);
+
+        let rsp = actix_test::call_service(&app, upload("/upload-desktop", "a.txt", b"hi\n", None).to_request()).await;
+        assert_eq!(rsp.status(), StatusCode::OK);
+
+        let files = state.lock_files();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].name, "a.txt");
+        assert_eq!(files[0].size, 3);
+    }
+
+    #[actix_web::test]
+    async fn oversized_upload_is_rejected_with_413() {
+        let state = server("too-large", Config { size_limit: 16, ..Config::default() });
+        let app = app!(state);
+
+        let bytes = [0u8; 64];
+        for size in [Some(bytes.len()), None] {
+            let rsp = actix_test::call_service(&app, upload("/upload-desktop", "big.bin", &bytes, size).to_request()).await;
+            assert_eq!(rsp.status(), StatusCode::PAYLOAD_TOO_LARGE, "size field: {size:?}");
+        }
+        assert!(state.lock_files().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn downloads_dir_is_recreated_between_uploads() {
+        let state = server("recreate", Config::default());
+        let app = app!(state);
+
+        let rsp = actix_test::call_service(&app, upload("/upload-mobile", "a.txt", b"first", Some(5)).to_request()).await;
+        assert_eq!(rsp.status(), StatusCode::OK);
+
+        fs::remove_dir_all(&state.config.downloads_dir).unwrap();
+
+        let rsp = actix_test::call_service(&app, upload("/upload-mobile", "b.txt", b"second", Some(6)).to_request()).await;
+        assert_eq!(rsp.status(), StatusCode::OK);
+        assert_eq!(fs::read(state.config.downloads_dir.join("b.txt")).unwrap(), b"second");
+    }
+
+    #[actix_web::test]
+    async fn oversized_mobile_upload_is_rejected_with_413() {
+        let state = server("too-large-mobile", Config { size_limit: 16, ..Config::default() });
+        let app = app!(state);
+
+        let bytes = [0u8; 64];
+        for size in [Some(bytes.len()), None] {
+            let rsp = actix_test::call_service(&app, upload("/upload-mobile", "big.bin", &bytes, size).to_request()).await;
+            assert_eq!(rsp.status(), StatusCode::PAYLOAD_TOO_LARGE, "size field: {size:?}");
+        }
+        assert!(!state.config.downloads_dir.join("big.bin").exists());
+    }
+
+    #[actix_web::test]
+    async fn progress_reaches_every_subscriber_of_a_file() {
+        let state = server("subscribers", Config::default());
+
+        let mut first = state.subscribe_progress("a.bin".to_owned(), false);
+        let mut second = state.subscribe_progress("a.bin".to_owned(), false);
+        assert_eq!(state.clients.len(), 1);
+
+        state.clients.get("a.bin").unwrap().sender.send(ProgressEvent { progress: 42, ..Default::default() }).unwrap();
+        for rx in [&mut first, &mut second] {
+            rx.changed().await.unwrap();
+            assert_eq!(rx.borrow_and_update().progress, 42);
+        }
+    }
+
+    #[test]
+    fn sanitize_file_name_keeps_only_the_basename() {
+        assert_eq!(sanitize_file_name("../x").unwrap(), "x");
+        assert_eq!(sanitize_file_name("..\\x").unwrap(), "x");
+        assert_eq!(sanitize_file_name("../../etc/passwd").unwrap(), "passwd");
+        assert_eq!(sanitize_file_name("C:\\Windows\\win.ini").unwrap(), "win.ini");
+        assert_eq!(sanitize_file_name("/abs/a.txt").unwrap(), "a.txt");
+
+        for traversal in ["..", "../..", "..\\..", "a/", "a\\", ""] {
+            assert!(sanitize_file_name(traversal).is_err(), "{traversal:?}")
+        }
+    }
+
+    #[test]
+    fn sanitize_file_name_rejects_names_past_the_filesystem_limit() {
+        let longest = format!("{stem}.txt", stem = "a".repeat(MAX_FILE_NAME_LEN - 4));
+        assert_eq!(sanitize_file_name(&longest).unwrap(), longest);
+        assert!(sanitize_file_name(&"a".repeat(300)).is_err());
+        assert!(sanitize_file_name(&format!("{stem}.txt", stem = "a".repeat(296))).is_err());
+    }
+
+    #[actix_web::test]
+    async fn upload_with_a_300_char_name_is_rejected_with_400() {
+        let state = server("long-name", Config::default());
+        let app = app!(state);
+
+        let name = "a".repeat(300);
+        for uri in ["/upload-desktop", "/upload-mobile"] {
+            let rsp = actix_test::call_service(&app, upload(uri, &name, b"hi\n", Some(3)).to_request()).await;
+            assert_eq!(rsp.status(), StatusCode::BAD_REQUEST, "{uri}");
+        }
+        assert!(state.lock_files().is_empty());
+    }
+
+    #[test]
+    fn numbered_name_goes_before_the_extension() {
+        let names = (0..3).map(|n| numbered_name("a.txt", n)).collect::<Vec::<_>>();
+        assert_eq!(names, ["a.txt", "a (1).txt", "a (2).txt"]);
+        assert_eq!(numbered_name("a.tar.gz", 1), "a (1).tar.gz");
+        assert_eq!(numbered_name(".bashrc", 1), ".bashrc (1)");
+        assert_eq!(numbered_name("README", 2), "README (2)");
+    }
+
+    #[actix_web::test]
+    async fn colliding_uploads_are_numbered() {
+        let state = server("collisions", Config::default());
+        let app = app!(state);
+
+        for uri in ["/upload-desktop", "/upload-mobile"] {
+            for _ in 0..3 {
+                let rsp = actix_test::call_service(&app, upload(uri, "a.txt", b"hi\n", Some(3)).to_request()).await;
+                assert_eq!(rsp.status(), StatusCode::OK, "{uri}");
+            }
+        }
+
+        let stored = state.lock_files().iter().map(|file| file.name.clone()).collect::<Vec::<_>>();
+        assert_eq!(stored, ["a.txt", "a (1).txt", "a (2).txt"]);
+        for name in ["a.txt", "a (1).txt", "a (2).txt"] {
+            assert!(state.config.downloads_dir.join(name).exists(), "{name}")
+        }
+    }
+
+    #[actix_web::test]
+    async fn progress_events_are_valid_json() {
+        let state = server("sse", Config::default());
+        let app = app!(state);
+
+        let rq = actix_test::TestRequest::get().uri("/progress/a.bin").insert_header(("User-Agent", "droppa-test")).to_request();
+        let rsp = actix_test::call_service(&app, rq).await;
+        assert_eq!(rsp.status(), StatusCode::OK);
+
+        // Removing the client is what ends the stream, after the last event went out
+        let (_, client) = state.clients.remove("a.bin").unwrap();
+        client.sender.send(ProgressEvent { progress: 100, state: Some(UploadState::Done), ..Default::default() }).unwrap();
+        drop(client);
+
+        let body = actix_test::read_body(rsp).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        let events = body.lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .map(|data| serde_json::from_str::<serde_json::Value>(data).unwrap_or_else(|e| panic!("{data}: {e}")))
+            .collect::<Vec::<_>>();
+
+        let last = events.last().unwrap();
+        assert_eq!(last["progress"], 100);
+        assert_eq!(last["state"], "done");
+    }
+
+    #[actix_web::test]
+    async fn appends_are_capped_and_counted_against_the_budget() {
+        let state = server("append", Config { memory_budget: 2 * MAX_APPEND_LEN, ..Config::default() });
+        let app = app!(state);
+
+        for line in ["one\n", "two\n"] {
+            let rsp = actix_test::call_service(&app, actix_test::TestRequest::post().uri("/append/log.txt").set_payload(line).to_request()).await;
+            assert_eq!(rsp.status(), StatusCode::OK);
+        }
+        assert_eq!(fs::read(state.config.downloads_dir.join("log.txt")).unwrap(), b"one\ntwo\n");
+
+        let rq = actix_test::TestRequest::post().uri("/append/log.txt").set_payload(vec![b'x'; MAX_APPEND_LEN + 1]).to_request();
+        assert_eq!(actix_test::call_service(&app, rq).await.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        // What the desktop uploads hold is all the budget there is
+        let _held = state.memory_budget.claim(2 * MAX_APPEND_LEN - 4).unwrap();
+        let rq = actix_test::TestRequest::post().uri("/append/log.txt").set_payload("three\n").to_request();
+        assert_eq!(actix_test::call_service(&app, rq).await.status(), StatusCode::INSUFFICIENT_STORAGE);
+
+        assert_eq!(fs::read(state.config.downloads_dir.join("log.txt")).unwrap(), b"one\ntwo\n");
+        assert_eq!(state.memory_budget.used(), 2 * MAX_APPEND_LEN - 4);
+    }
 }
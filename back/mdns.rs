@@ -0,0 +1,41 @@
+use std::net::IpAddr;
+use std::time::Duration;
+
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+
+const SERVICE_TYPE: &str = "_http._tcp.local.";
+const INSTANCE_NAME: &str = "droppa";
+const HOST_NAME: &str = "droppa.local.";
+
+pub struct Advertisement {
+    daemon: ServiceDaemon,
+    fullname: String
+}
+
+/// Announces `droppa._http._tcp.local` on `ip:port`, and answers `droppa.local` lookups, until stopped.
+pub fn advertise(ip: IpAddr, port: u16) -> Result::<Advertisement, mdns_sd::Error> {
+    let daemon = ServiceDaemon::new()?;
+    let info = ServiceInfo::new(SERVICE_TYPE, INSTANCE_NAME, HOST_NAME, ip, port, &[("path", "/")][..])?;
+
+    let fullname = info.get_fullname().to_owned();
+    daemon.register(info)?;
+
+    Ok(Advertisement { daemon, fullname })
+}
+
+impl Advertisement {
+    #[inline(always)]
+    pub fn fullname(&self) -> &str {
+        &self.fullname
+    }
+
+    // Sends the goodbye packets, so that browsers forget about us right away instead of when the record expires.
+    pub fn stop(self) {
+        match self.daemon.unregister(&self.fullname) {
+            Ok(status) => _ = status.recv_timeout(Duration::from_secs(1)),
+            Err(e) => log::error!("could not unregister mDNS service: {e}")
+        }
+
+        _ = self.daemon.shutdown()
+    }
+}
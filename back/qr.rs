@@ -26,6 +26,30 @@ pub fn gen_qr_png_bytes(qr: &QrCode) -> Result::<Vec::<u8>, ()> {
     unsafe { write_png_to_memory(&image, img_size as _, img_size as _) }
 }
 
+/// Prints `qr` to stdout with half blocks, two rows of modules per line of text.
+/// Colors are set explicitly, since a code that's light on dark won't scan with every phone.
+pub fn print_qr_to_terminal(qr: &QrCode) {
+    let border = BORDER as i32;
+    let range = -border..qr.size() + border;
+
+    let mut out = String::new();
+    for y in range.clone().step_by(2) {
+        out.push_str("\x1b[30;107m");
+        for x in range.clone() {
+            // Out of bounds modules are light, that takes care of the border and an odd last row
+            out.push(match (qr.get_module(x, y), qr.get_module(x, y + 1)) {
+                (true, true)   => '█',
+                (true, false)  => '▀',
+                (false, true)  => '▄',
+                (false, false) => ' '
+            })
+        }
+        out.push_str("\x1b[0m\n")
+    }
+
+    print!("{out}")
+}
+
 pub unsafe fn write_png_to_memory(image: &[u8], width: i32, height: i32) -> Result::<Vec::<u8>, ()> {
     let mut out_len = 0;
 
@@ -44,3 +68,25 @@ pub unsafe fn write_png_to_memory(image: &[u8], width: i32, height: i32) -> Resu
         Err(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use qrcodegen::QrCodeEcc;
+
+    #[test]
+    fn png_is_sized_after_the_code() {
+        let url = format!("https://192.168.100.100:6969/?fp={fp}&pw={pw}", fp = "ab".repeat(32), pw = "x".repeat(64));
+        let qr = QrCode::encode_text(&url, QrCodeEcc::High).unwrap();
+        assert!(qr.size() > 25);
+
+        let png = gen_qr_png_bytes(&qr).unwrap();
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+
+        // IHDR is always the first chunk, width and height open it
+        let width = u32::from_be_bytes(png[16..20].try_into().unwrap()) as usize;
+        let height = u32::from_be_bytes(png[20..24].try_into().unwrap()) as usize;
+        let expected = (qr.size() as usize + 2 * BORDER) * SCALE;
+        assert_eq!((width, height), (expected, expected));
+    }
+}
@@ -0,0 +1,55 @@
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+
+// Past this many clients, the ones that are idle get forgotten
+const MAX_BUCKETS: usize = 1024;
+
+struct Bucket {
+    tokens: f64,
+    refilled: Instant
+}
+
+/// Token bucket per client IP: every request takes a token, `rate` of them come back per second,
+/// and at most `burst` can pile up, that's `rate`, but at least one.
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    buckets: DashMap::<IpAddr, Bucket>
+}
+
+impl RateLimiter {
+    #[inline(always)]
+    pub fn new(rate: f64) -> Self {
+        Self { rate, burst: rate.max(1.0), buckets: DashMap::new() }
+    }
+
+    /// Takes a token from `ip`'s bucket, or tells how long to wait until there's one.
+    pub fn check(&self, ip: IpAddr) -> Result::<(), Duration> {
+        if self.buckets.len() > MAX_BUCKETS {
+            self.forget_idle()
+        }
+
+        let now = Instant::now();
+        let mut bucket = self.buckets.entry(ip).or_insert_with(|| Bucket { tokens: self.burst, refilled: now });
+
+        let elapsed = now.duration_since(bucket.refilled).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.refilled = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(())
+        }
+
+        Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+    }
+
+    // A full bucket is the same as no bucket at all.
+    fn forget_idle(&self) {
+        let now = Instant::now();
+        let refill = Duration::from_secs_f64(self.burst / self.rate);
+        self.buckets.retain(|_, bucket| now.duration_since(bucket.refilled) < refill)
+    }
+}
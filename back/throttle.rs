@@ -0,0 +1,29 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// How far ahead of the rate a transfer may get, so that small writes don't each wait on their own
+const BURST: Duration = Duration::from_millis(250);
+
+/// Token bucket over bytes, `--max-rate`: `rate` bytes come back per second, and up to `BURST` worth of them
+/// can be used up at once. Kept as the time at which everything taken so far will have been paid for.
+pub struct Throttle {
+    rate: f64,
+    paid_until: Mutex::<Instant>
+}
+
+impl Throttle {
+    #[inline(always)]
+    pub fn new(rate: usize) -> Self {
+        Self { rate: rate as f64, paid_until: Mutex::new(Instant::now()) }
+    }
+
+    /// Takes `bytes`, and tells how long to wait for them to be within the rate.
+    pub fn take(&self, bytes: usize) -> Duration {
+        let now = Instant::now();
+        let mut paid_until = self.paid_until.lock().unwrap();
+
+        // Time spent idle isn't saved up, `BURST` is all the slack there is
+        *paid_until = (*paid_until).max(now) + Duration::from_secs_f64(bytes as f64 / self.rate);
+        paid_until.saturating_duration_since(now).saturating_sub(BURST)
+    }
+}
@@ -0,0 +1,33 @@
+use std::net::IpAddr;
+
+use sha2::{Sha256, Digest};
+use rustls::ServerConfig;
+use rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+
+pub struct SelfSigned {
+    pub config: ServerConfig,
+    // SHA-256 of the certificate, what browsers show in the certificate viewer
+    pub fingerprint: [u8; 32]
+}
+
+impl SelfSigned {
+    // `AB:CD:..`, the way browsers print it
+    pub fn fingerprint_pretty(&self) -> String {
+        self.fingerprint.iter().map(|b| format!("{b:02X}")).collect::<Vec::<_>>().join(":")
+    }
+}
+
+/// Generates a fresh certificate for `ip` on every start, there's no CA that could vouch for a LAN address anyway.
+pub fn self_signed(ip: IpAddr) -> Result::<SelfSigned, String> {
+    let rcgen::CertifiedKey { cert, key_pair } = rcgen::generate_simple_self_signed(vec![ip.to_string()]).map_err(|e| e.to_string())?;
+
+    let fingerprint = Sha256::digest(cert.der()).into();
+    let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key_pair.serialize_der()));
+
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(vec![cert.der().clone()], key)
+        .map_err(|e| e.to_string())?;
+
+    Ok(SelfSigned { config, fingerprint })
+}
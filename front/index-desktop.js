@@ -1,3 +1,11 @@
+// Set when droppa runs with `--password`, the QR code puts it in the page's URL
+const token = new URLSearchParams(window.location.search).get("token");
+
+function withToken(url) {
+  if (!token) return url;
+  return `${url}${url.includes("?") ? "&" : "?"}token=${encodeURIComponent(token)}`;
+}
+
 let globalFiles = [];
 
 let downloadFiles = new Map();
@@ -12,7 +20,7 @@ function connectSSE() {
 
   console.log("Establishing SSE connection...");
 
-  eventSource = new EventSource("/download-files-progress-desktop");
+  eventSource = new EventSource(withToken("/download-files-progress-desktop"));
 
   eventSource.onopen = () => {
     console.log("SSE connection established");
@@ -20,12 +28,6 @@ function connectSSE() {
 
   eventSource.onmessage = (event) => {
     console.log("Received SSE message:", event.data);
-    if (event.data === "CONNECTION_REPLACED") {
-      console.log("Connection replaced by the server.");
-      eventSource.close();
-      return;
-    }
-
     const eventData = JSON.parse(event.data);
 
     console.log(downloadFiles);
@@ -46,6 +48,12 @@ function connectSSE() {
     });
   };
 
+  // Another tab took the stream over, don't reconnect and take it back
+  eventSource.addEventListener("replaced", () => {
+    console.log("Connection replaced by the server.");
+    eventSource.close();
+  });
+
   eventSource.onerror = (error) => {
     console.error("SSE connection error:", error);
     // Close the connection to avoid endless reconnect attempts
@@ -64,12 +72,46 @@ window.addEventListener("beforeunload", () => {
   }
 });
 
+// The same URL the QR code carries, for phones that can't scan it
+function showServerUrl(span) {
+  fetch(withToken("/info"))
+    .then((response) => (response.ok ? response.json() : null))
+    .then((info) => {
+      if (info) {
+        span.textContent = `QR code for your phone, or open ${info.url}`;
+      }
+    })
+    .catch((error) => console.error(error));
+}
+
+// Only there with `--wifi-ssid`, a 404 just means there's nothing to show
+function showWifiQrCode() {
+  const container = document.getElementById("wifi-qrcode-container");
+  fetch(withToken("/wifi-qr.png"))
+    .then((response) => (response.ok ? response.blob() : null))
+    .then((blob) => {
+      if (!blob) {
+        return;
+      }
+
+      const img = document.createElement("img");
+      const span = document.createElement("span");
+      img.src = URL.createObjectURL(blob);
+      span.textContent = "Scan first to join the Wi-Fi";
+      container.appendChild(img);
+      container.appendChild(span);
+      container.style.display = "flex";
+    })
+    .catch((error) => console.error(error));
+}
+
 window.addEventListener("load", () => {
   connectSSE();
+  showWifiQrCode();
 
   const qrcodeContainer = document.getElementById("qrcode-container");
 
-  fetch("/qr.png")
+  fetch(withToken("/qr.png"))
     .then((response) => {
       if (!response.ok) {
         throw new Error("Failed to fetch QR code");
@@ -84,6 +126,7 @@ window.addEventListener("load", () => {
       qrcodeContainer.innerHTML = "";
       qrcodeContainer.appendChild(img);
       qrcodeContainer.appendChild(span);
+      showServerUrl(span);
     })
     .catch((error) => {
       qrcodeContainer.innerHTML = "<span>Error loading QR Code</span>";
@@ -134,15 +177,16 @@ document
       return;
     }
 
-    const files = Array.from(ev.dataTransfer.items);
-    files.forEach((file) => {
+    const files = await droppedFiles(ev.dataTransfer.items);
+    files.forEach(({ file, dir }) => {
       const { message, fileNameSpan, messageStatusDiv } = createMessage(
-        file.getAsFile(),
+        file,
         "upload"
       );
       const fullFileObject = {
         status: "idle",
-        file: file.getAsFile(),
+        file: file,
+        dir: dir,
         message: message,
         fileNameSpan: fileNameSpan,
         messageStatusDiv: messageStatusDiv,
@@ -151,6 +195,43 @@ document
     });
   });
 
+// Dropped folders are walked for their files, each one keeping the folder it was in, e.g. `photos/2023`
+async function droppedFiles(items) {
+  // The entries have to be taken before the first `await`, the drop's data is gone after it
+  const dropped = Array.from(items).map((item) => ({
+    entry: item.webkitGetAsEntry ? item.webkitGetAsEntry() : null,
+    file: item.getAsFile(),
+  }));
+
+  const files = [];
+  async function walk(entry, dir) {
+    if (entry.isFile) {
+      const file = await new Promise((resolve, reject) => entry.file(resolve, reject));
+      files.push({ file, dir });
+    } else if (entry.isDirectory) {
+      const path = dir ? `${dir}/${entry.name}` : entry.name;
+      const reader = entry.createReader();
+      // Handed out in batches, until an empty one
+      for (;;) {
+        const entries = await new Promise((resolve, reject) => reader.readEntries(resolve, reject));
+        if (!entries.length) break;
+        for (const child of entries) {
+          await walk(child, path);
+        }
+      }
+    }
+  }
+
+  for (const { entry, file } of dropped) {
+    if (entry) {
+      await walk(entry, "");
+    } else if (file) {
+      files.push({ file, dir: "" });
+    }
+  }
+  return files;
+}
+
 document
   .getElementById("upload-button")
   .addEventListener("click", async (e) => {
@@ -187,6 +268,54 @@ document.getElementById("file-input").addEventListener("change", (e) => {
   });
 });
 
+// Pasted screenshots go up right away, text pasted into a text field is left to it
+document.addEventListener("paste", (e) => {
+  const images = Array.from(e.clipboardData?.items ?? [])
+    .filter((item) => item.kind === "file" && item.type.startsWith("image/"))
+    .map((item) => item.getAsFile())
+    .filter(Boolean);
+
+  if (images.length === 0) {
+    return;
+  }
+
+  e.preventDefault();
+  images.forEach(uploadPastedImage);
+});
+
+async function uploadPastedImage(image) {
+  const { message, fileNameSpan, messageStatusDiv } = createMessage(
+    { name: "pasted image" },
+    "upload"
+  );
+
+  try {
+    const response = await fetch(withToken("/upload-paste"), {
+      method: "POST",
+      headers: { "Content-Type": image.type },
+      body: image,
+    });
+
+    if (!response.ok) {
+      console.log(await response.text());
+      messageStatusDiv.textContent = `FAILURE`;
+      message.className = "status-message error";
+      return;
+    }
+
+    // Named by the server, after when it was pasted
+    const { files } = await response.json();
+    fileNameSpan.textContent = files[0].name;
+    fileNameSpan.title = files[0].name;
+    messageStatusDiv.textContent = `SUCCESS`;
+    message.className = "status-message success";
+  } catch (error) {
+    console.error(error);
+    messageStatusDiv.textContent = `FAILURE`;
+    message.className = "status-message error";
+  }
+}
+
 function createMessage(file, transmissionType) {
   const statusDiv = document.getElementById(`${transmissionType}_status`);
   const message = document.createElement("div");
@@ -257,6 +386,9 @@ async function uploadFile(fileObject) {
   }
 
   const formData = new FormData();
+  if (fileObject.dir) {
+    formData.append("path", fileObject.dir);
+  }
   formData.append("size", fileObject.file.size);
   formData.append("file", fileObject.file);
 
@@ -268,7 +400,7 @@ async function uploadFile(fileObject) {
     trackProgress(eventSource, fileObject);
 
     console.log("Sending upload request...");
-    const response = await fetch("/upload-desktop", {
+    const response = await fetch(withToken("/upload-desktop"), {
       method: "POST",
       body: formData,
     });
@@ -292,7 +424,7 @@ async function uploadFile(fileObject) {
 
 async function openProgressConnection(file) {
   return new Promise((resolve, reject) => {
-    const eventSource = new EventSource(`/progress/${file.name}`);
+    const eventSource = new EventSource(withToken(`/progress/${file.name}`));
 
     eventSource.onopen = () => {
       console.log(`Progress connection for ${file.name} established.`);
@@ -310,29 +442,60 @@ async function openProgressConnection(file) {
   });
 }
 
+function formatRate({ bps, eta_secs }) {
+  if (!bps) return "";
+  const units = ["B", "KB", "MB", "GB"];
+  let rate = bps, unit = 0;
+  while (rate >= 1024 && unit < units.length - 1) {
+    rate /= 1024;
+    unit++;
+  }
+  const eta = eta_secs == null ? "" : `, ${eta_secs}s left`;
+  return ` (${rate.toFixed(1)} ${units[unit]}/s${eta})`;
+}
+
 async function trackProgress(eventSource, fileObject) {
   let isComplete = false;
 
   eventSource.onmessage = (event) => {
     const progressData = JSON.parse(event.data);
+    // Zips end with a plain message, uploads with a `done` event
+    if (["cancelled", "done", "failed"].includes(progressData.state)) {
+      finish(progressData.state);
+      return;
+    }
+
     if (progressData.progress !== undefined) {
       const progress = progressData.progress;
       fileObject.message.className = "status-message progress";
 
-      fileObject.messageStatusDiv.textContent = ` ${progress}%`;
+      const rate = progressData.state === "paused" ? " (paused)" : formatRate(progressData);
+      fileObject.messageStatusDiv.textContent = ` ${progress}%${rate}`;
 
       fileObject.status = "progress";
-
-      if (progress === 100) {
-        fileObject.status = "success";
-        fileObject.messageStatusDiv.textContent = `SUCCESS`;
-        fileObject.message.className = "status-message success";
-        isComplete = true;
-        eventSource.close();
-      }
     }
   };
 
+  // The last event of the stream, carrying how the upload ended
+  eventSource.addEventListener("done", (event) => {
+    finish(JSON.parse(event.data).state);
+  });
+
+  function finish(state) {
+    isComplete = true;
+    eventSource.close();
+
+    if (state === "done") {
+      fileObject.status = "success";
+      fileObject.messageStatusDiv.textContent = `SUCCESS`;
+      fileObject.message.className = "status-message success";
+    } else {
+      fileObject.status = state;
+      fileObject.messageStatusDiv.textContent = state === "cancelled" ? `CANCELLED` : `FAILURE`;
+      fileObject.message.className = "status-message error";
+    }
+  }
+
   eventSource.onerror = (error) => {
     if (!isComplete) {
       fileObject.message.textContent = `FAILURE`;
@@ -341,3 +504,39 @@ async function trackProgress(eventSource, fileObject) {
     }
   };
 }
+
+const clipboardText = document.getElementById("clipboard-text");
+
+document.getElementById("clipboard-button").addEventListener("click", async () => {
+  try {
+    const response = await fetch(withToken("/clipboard"), {
+      method: "POST",
+      headers: { "Content-Type": "text/plain; charset=utf-8" },
+      body: clipboardText.value,
+    });
+    if (!response.ok) {
+      console.error("Could not share clipboard:", await response.text());
+    }
+  } catch (error) {
+    console.error("Could not share clipboard:", error);
+  }
+});
+
+function connectClipboard() {
+  const clipboardSource = new EventSource(withToken("/clipboard-events"));
+
+  clipboardSource.onmessage = (event) => {
+    const { text } = JSON.parse(event.data);
+    // Don't pull the text from under someone who is typing
+    if (document.activeElement !== clipboardText) {
+      clipboardText.value = text;
+    }
+  };
+
+  clipboardSource.onerror = () => {
+    clipboardSource.close();
+    setTimeout(connectClipboard, 2500);
+  };
+}
+
+connectClipboard();
@@ -1,3 +1,11 @@
+// Set when droppa runs with `--password`, the QR code puts it in the page's URL
+const token = new URLSearchParams(window.location.search).get("token");
+
+function withToken(url) {
+  if (!token) return url;
+  return `${url}${url.includes("?") ? "&" : "?"}token=${encodeURIComponent(token)}`;
+}
+
 let globalFiles = [];
 
 let downloadFiles = new Map();
@@ -12,7 +20,7 @@ function connectSSE() {
 
   console.log("Establishing SSE connection...");
 
-  eventSource = new EventSource("/download-files-progress-mobile");
+  eventSource = new EventSource(withToken("/download-files-progress-mobile"));
 
   eventSource.onopen = () => {
     console.log("SSE connection established");
@@ -20,12 +28,6 @@ function connectSSE() {
 
   eventSource.onmessage = (event) => {
     console.log("Received SSE message:", event.data);
-    if (event.data === "CONNECTION_REPLACED") {
-      console.log("Connection replaced by the server.");
-      eventSource.close();
-      return;
-    }
-
     const eventData = JSON.parse(event.data);
 
     console.log(downloadFiles);
@@ -46,6 +48,12 @@ function connectSSE() {
     });
   };
 
+  // Another tab took the stream over, don't reconnect and take it back
+  eventSource.addEventListener("replaced", () => {
+    console.log("Connection replaced by the server.");
+    eventSource.close();
+  });
+
   eventSource.onerror = (error) => {
     console.error("SSE connection error:", error);
     // Close the connection to avoid endless reconnect attempts
@@ -141,15 +149,16 @@ document
       return;
     }
 
-    const files = Array.from(ev.dataTransfer.items);
-    files.forEach((file) => {
+    const files = await droppedFiles(ev.dataTransfer.items);
+    files.forEach(({ file, dir }) => {
       const { message, fileNameSpan, messageStatusDiv } = createMessage(
-        file.getAsFile(),
+        file,
         "upload"
       );
       const fullFileObject = {
         status: "idle",
-        file: file.getAsFile(),
+        file: file,
+        dir: dir,
         message: message,
         fileNameSpan: fileNameSpan,
         messageStatusDiv: messageStatusDiv,
@@ -158,6 +167,43 @@ document
     });
   });
 
+// Dropped folders are walked for their files, each one keeping the folder it was in, e.g. `photos/2023`
+async function droppedFiles(items) {
+  // The entries have to be taken before the first `await`, the drop's data is gone after it
+  const dropped = Array.from(items).map((item) => ({
+    entry: item.webkitGetAsEntry ? item.webkitGetAsEntry() : null,
+    file: item.getAsFile(),
+  }));
+
+  const files = [];
+  async function walk(entry, dir) {
+    if (entry.isFile) {
+      const file = await new Promise((resolve, reject) => entry.file(resolve, reject));
+      files.push({ file, dir });
+    } else if (entry.isDirectory) {
+      const path = dir ? `${dir}/${entry.name}` : entry.name;
+      const reader = entry.createReader();
+      // Handed out in batches, until an empty one
+      for (;;) {
+        const entries = await new Promise((resolve, reject) => reader.readEntries(resolve, reject));
+        if (!entries.length) break;
+        for (const child of entries) {
+          await walk(child, path);
+        }
+      }
+    }
+  }
+
+  for (const { entry, file } of dropped) {
+    if (entry) {
+      await walk(entry, "");
+    } else if (file) {
+      files.push({ file, dir: "" });
+    }
+  }
+  return files;
+}
+
 document
   .getElementById("upload-button")
   .addEventListener("click", async (e) => {
@@ -196,7 +242,7 @@ document.getElementById("file-input").addEventListener("change", (e) => {
 
 async function openZipProgressConnection() {
   return new Promise((resolve, reject) => {
-    const eventSource = new EventSource(`/zipping-progress`);
+    const eventSource = new EventSource(withToken(`/zipping-progress`));
 
     eventSource.onopen = () => {
       console.log(`Progress connection for zip established.`);
@@ -265,7 +311,7 @@ document
 
       trackProgress(eventSource, fullFileObject);
 
-      const response = await fetch("/download-files-mobile");
+      const response = await fetch(withToken("/download-files-mobile"));
 
       const contentLength = response.headers.get("Content-Length");
       const total = contentLength ? parseInt(contentLength, 10) : 0;
@@ -295,8 +341,14 @@ document
         chunks.push(value);
         loaded += value.length;
 
+        // Zips are streamed as they're compressed, without a Content-Length,
+        // then the `/zipping-progress` events that `trackProgress` shows are what moves the bar
+        if (!total) {
+          continue;
+        }
+
         // Calculate progress
-        const progress = total ? Math.floor((loaded / total) * 100) : 0;
+        const progress = Math.floor((loaded / total) * 100);
 
         downloadFiles.get(file.name).progress = progress.toString();
 
@@ -365,10 +417,28 @@ async function uploadFile(fileObject) {
   }
 
   const formData = new FormData();
+  if (fileObject.dir) {
+    formData.append("path", fileObject.dir);
+  }
   formData.append("size", fileObject.file.size);
   formData.append("file", fileObject.file);
 
   try {
+    // No point in sending a few GB just to find out there's no room for them
+    const check = await fetch(withToken("/upload-check"), {
+      method: "POST",
+      headers: { "Content-Type": "application/json" },
+      body: JSON.stringify({ name: fileObject.file.name, size: fileObject.file.size }),
+    });
+
+    if (!check.ok) {
+      const errorText = await check.text();
+      console.log(errorText);
+      fileObject.messageStatusDiv.textContent = `FAILURE`;
+      fileObject.message.className = "status-message error";
+      return;
+    }
+
     console.log(`Opening progress connection for ${fileObject.file.name}`);
     const eventSource = await openProgressConnection(fileObject.file);
 
@@ -376,7 +446,7 @@ async function uploadFile(fileObject) {
     trackProgress(eventSource, fileObject);
 
     console.log("Sending upload request...");
-    const response = await fetch("/upload-mobile", {
+    const response = await fetch(withToken("/upload-mobile"), {
       method: "POST",
       body: formData,
     });
@@ -400,7 +470,7 @@ async function uploadFile(fileObject) {
 
 async function openProgressConnection(file) {
   return new Promise((resolve, reject) => {
-    const eventSource = new EventSource(`/progress/${file.name}`);
+    const eventSource = new EventSource(withToken(`/progress/${file.name}`));
 
     eventSource.onopen = () => {
       console.log(`Progress connection for ${file.name} established.`);
@@ -418,30 +488,61 @@ async function openProgressConnection(file) {
   });
 }
 
+function formatRate({ bps, eta_secs }) {
+  if (!bps) return "";
+  const units = ["B", "KB", "MB", "GB"];
+  let rate = bps, unit = 0;
+  while (rate >= 1024 && unit < units.length - 1) {
+    rate /= 1024;
+    unit++;
+  }
+  const eta = eta_secs == null ? "" : `, ${eta_secs}s left`;
+  return ` (${rate.toFixed(1)} ${units[unit]}/s${eta})`;
+}
+
 async function trackProgress(eventSource, fileObject) {
   let isComplete = false;
 
   eventSource.onmessage = (event) => {
     console.log("Received SSE message:", event.data);
     const progressData = JSON.parse(event.data);
+    // Zips end with a plain message, uploads with a `done` event
+    if (["cancelled", "done", "failed"].includes(progressData.state)) {
+      finish(progressData.state);
+      return;
+    }
+
     if (progressData.progress !== undefined) {
       const progress = progressData.progress;
       fileObject.message.className = "status-message progress";
 
-      fileObject.messageStatusDiv.textContent = ` ${progress}%`;
+      const rate = progressData.state === "paused" ? " (paused)" : formatRate(progressData);
+      fileObject.messageStatusDiv.textContent = ` ${progress}%${rate}`;
 
       fileObject.status = "progress";
-
-      if (progress === 100) {
-        fileObject.status = "success";
-        fileObject.messageStatusDiv.textContent = `SUCCESS`;
-        fileObject.message.className = "status-message success";
-        isComplete = true;
-        eventSource.close();
-      }
     }
   };
 
+  // The last event of the stream, carrying how the upload ended
+  eventSource.addEventListener("done", (event) => {
+    finish(JSON.parse(event.data).state);
+  });
+
+  function finish(state) {
+    isComplete = true;
+    eventSource.close();
+
+    if (state === "done") {
+      fileObject.status = "success";
+      fileObject.messageStatusDiv.textContent = `SUCCESS`;
+      fileObject.message.className = "status-message success";
+    } else {
+      fileObject.status = state;
+      fileObject.messageStatusDiv.textContent = state === "cancelled" ? `CANCELLED` : `FAILURE`;
+      fileObject.message.className = "status-message error";
+    }
+  }
+
   eventSource.onerror = (error) => {
     if (!isComplete) {
       fileObject.message.textContent = `FAILURE`;
@@ -450,3 +551,39 @@ async function trackProgress(eventSource, fileObject) {
     }
   };
 }
+
+const clipboardText = document.getElementById("clipboard-text");
+
+document.getElementById("clipboard-button").addEventListener("click", async () => {
+  try {
+    const response = await fetch(withToken("/clipboard"), {
+      method: "POST",
+      headers: { "Content-Type": "text/plain; charset=utf-8" },
+      body: clipboardText.value,
+    });
+    if (!response.ok) {
+      console.error("Could not share clipboard:", await response.text());
+    }
+  } catch (error) {
+    console.error("Could not share clipboard:", error);
+  }
+});
+
+function connectClipboard() {
+  const clipboardSource = new EventSource(withToken("/clipboard-events"));
+
+  clipboardSource.onmessage = (event) => {
+    const { text } = JSON.parse(event.data);
+    // Don't pull the text from under someone who is typing
+    if (document.activeElement !== clipboardText) {
+      clipboardText.value = text;
+    }
+  };
+
+  clipboardSource.onerror = () => {
+    clipboardSource.close();
+    setTimeout(connectClipboard, 2500);
+  };
+}
+
+connectClipboard();